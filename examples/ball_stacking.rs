@@ -54,7 +54,7 @@ fn spawn_balls(
         })
         .insert_bundle(StaticBoxBundle {
             pos: Pos(Vec2::new(0., -4.)),
-            collider: BoxCollider { size },
+            collider: Collider::Box(BoxCollider { size }),
             ..Default::default()
         });
 
@@ -67,6 +67,11 @@ fn spawn_balls(
                 2. * radius * i as f32 - 2.,
             );
             let vel = Vec2::ZERO;
+            let collider = Collider::Circle(CircleCollider { radius });
+            let mass = Mass(1.);
+            let inertia = Inertia {
+                inv: collider.inertia_inv_from_mass_inv(1. / mass.0),
+            };
             commands
                 .spawn_bundle(PbrBundle {
                     mesh: sphere.clone(),
@@ -79,7 +84,9 @@ fn spawn_balls(
                     ..Default::default()
                 })
                 .insert_bundle(ParticleBundle {
-                    collider: CircleCollider { radius },
+                    collider,
+                    inertia,
+                    mass,
                     ..ParticleBundle::new_with_pos_and_vel(pos, vel)
                 });
         }
@@ -50,7 +50,7 @@ fn startup(
         .insert_bundle(StaticBoxBundle {
             pos: Pos(Vec2::new(0., -3.)),
             rot: Rot::from_degrees(0.),
-            collider: BoxCollider { size },
+            collider: Collider::Box(BoxCollider { size }),
             ..Default::default()
         });
 
@@ -73,7 +73,7 @@ fn spawn_boxes(mut commands: Commands, materials: Res<Materials>, meshes: ResMut
     let vel = Vec2::new(random::<f32>() - 0.5, random::<f32>() - 0.5);
     let rot = random::<Rot>();
     let ang_vel = random::<f32>() * 2. - 1.;
-    let collider = BoxCollider { size };
+    let collider = Collider::Box(BoxCollider { size });
     let mass = Mass(1.);
     let inertia = Inertia {
         inv: collider.inertia_inv_from_mass_inv(1. / mass.0),
@@ -76,7 +76,7 @@ fn startup(
         })
         .insert_bundle(StaticBoxBundle {
             pos: Pos(Vec2::new(0., -3.)),
-            collider: BoxCollider { size },
+            collider: Collider::Box(BoxCollider { size }),
             ..Default::default()
         });
 
@@ -88,6 +88,11 @@ fn spawn_marbles(mut commands: Commands, materials: Res<Materials>, meshes: Res<
     let radius = 0.1;
     let pos = Vec2::new(random::<f32>() - 0.5, random::<f32>() - 0.5) * 0.5 + Vec2::Y * 3.;
     let vel = Vec2::new(random::<f32>() - 0.5, random::<f32>() - 0.5);
+    let collider = Collider::Circle(CircleCollider { radius });
+    let mass = Mass(1.);
+    let inertia = Inertia {
+        inv: collider.inertia_inv_from_mass_inv(1. / mass.0),
+    };
     commands
         .spawn_bundle(PbrBundle {
             mesh: meshes.sphere.clone(),
@@ -100,7 +105,9 @@ fn spawn_marbles(mut commands: Commands, materials: Res<Materials>, meshes: Res<
             ..Default::default()
         })
         .insert_bundle(ParticleBundle {
-            collider: CircleCollider { radius },
+            collider,
+            inertia,
+            mass,
             ..ParticleBundle::new_with_pos_and_vel(pos, vel)
         });
 }
@@ -0,0 +1,628 @@
+use std::{cmp::Ordering, marker::PhantomData};
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+
+use crate::Aabb;
+
+/// Which structure the plugin uses to find the pairs of bodies whose bounding boxes overlap.
+/// The `AabbTree` resource is kept up to date either way, see `SpatialQuery`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BroadPhase {
+    #[default]
+    SweepAndPrune,
+    AabbTree,
+}
+
+#[derive(Debug)]
+struct SweepEntry {
+    entity: Entity,
+    min: Vec2,
+    max: Vec2,
+    /// Position of the entity in the latest `update`, which the pairs are ordered by
+    order: usize,
+}
+
+/// Sort-and-sweep broad phase along the axis the bodies are spread out the most, so a tall
+/// stack is swept along y.
+///
+/// The sorted list is kept between steps. Bodies only move a little per step, so re-sorting it
+/// with an insertion sort is close to linear. When the axis changes or many bodies are added at
+/// once it is sorted from scratch instead.
+#[derive(Debug, Default)]
+pub(crate) struct SweepAndPrune {
+    entries: Vec<SweepEntry>,
+    /// 0 for x, 1 for y
+    axis: usize,
+}
+
+impl SweepAndPrune {
+    /// Updates the sorted list with the current bounding boxes. Entities that are no longer
+    /// given are dropped, new ones are added.
+    pub fn update(&mut self, aabbs: impl Iterator<Item = (Entity, Aabb)>) {
+        let mut bounds: HashMap<Entity, (Aabb, usize)> =
+            aabbs.enumerate().map(|(order, (entity, aabb))| (entity, (aabb, order))).collect();
+        self.entries.retain(|entry| bounds.contains_key(&entry.entity));
+        let added = bounds.len() - self.entries.len();
+        for entry in self.entries.iter_mut() {
+            let (aabb, order) = bounds.remove(&entry.entity).unwrap();
+            entry.min = aabb.min;
+            entry.max = aabb.max;
+            entry.order = order;
+        }
+        self.entries.extend(bounds.into_iter().map(|(entity, (aabb, order))| SweepEntry {
+            entity,
+            min: aabb.min,
+            max: aabb.max,
+            order,
+        }));
+
+        let axis = self.spread_axis();
+        // Each added entry may have to travel the whole list, so past about log2(n) of them a
+        // full sort is cheaper
+        let log_len = (usize::BITS - self.entries.len().leading_zeros()) as usize;
+        if axis != self.axis || added > log_len {
+            self.axis = axis;
+            self.entries.sort_unstable_by(|a, b| Self::cmp(a, b, axis));
+        } else {
+            for i in 1..self.entries.len() {
+                let mut j = i;
+                while j > 0 && Self::cmp(&self.entries[j], &self.entries[j - 1], axis).is_lt() {
+                    self.entries.swap(j, j - 1);
+                    j -= 1;
+                }
+            }
+        }
+    }
+
+    /// Ties are broken by entity so the pair order does not depend on insertion order
+    fn cmp(a: &SweepEntry, b: &SweepEntry, axis: usize) -> Ordering {
+        a.min[axis].total_cmp(&b.min[axis]).then(a.entity.cmp(&b.entity))
+    }
+
+    /// The axis along which the centers of the boxes vary the most. Keeps the current one
+    /// unless the other is clearly better, so that it does not flip back and forth.
+    fn spread_axis(&self) -> usize {
+        let n = self.entries.len() as f32;
+        let (sum, sum_sq) = self.entries.iter().fold((Vec2::ZERO, Vec2::ZERO), |(sum, sum_sq), entry| {
+            let center = (entry.min + entry.max) / 2.;
+            (sum + center, sum_sq + center * center)
+        });
+        let variance = sum_sq / n - (sum / n) * (sum / n);
+        let other = 1 - self.axis;
+        if variance[other] > 2. * variance[self.axis] {
+            other
+        } else {
+            self.axis
+        }
+    }
+
+    /// Pushes every pair of overlapping bounding boxes, the larger entity first. The pairs come
+    /// in the order of a nested loop over the bodies as given to `update`, which is the order
+    /// the solver has always seen them in.
+    pub fn collect_pairs(&self, pairs: &mut Vec<(Entity, Entity)>) {
+        let (axis, other) = (self.axis, 1 - self.axis);
+        let mut ordered = Vec::new();
+        for (i, a) in self.entries.iter().enumerate() {
+            for b in self.entries[i + 1..].iter() {
+                if b.min[axis] > a.max[axis] {
+                    break;
+                }
+                if a.max[other] >= b.min[other] && a.min[other] <= b.max[other] {
+                    let (a, b) = if a.entity > b.entity { (a, b) } else { (b, a) };
+                    ordered.push((a.order, b.order, a.entity, b.entity));
+                }
+            }
+        }
+        ordered.sort_unstable();
+        pairs.extend(ordered.into_iter().map(|(_, _, entity_a, entity_b)| (entity_a, entity_b)));
+    }
+}
+
+const NULL_NODE: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+struct TreeNode {
+    /// Fat box for leaves, union of the children otherwise
+    aabb: Aabb,
+    /// The body's own box, leaves only
+    tight: Aabb,
+    parent: usize,
+    children: [usize; 2],
+    /// Zero for leaves, -1 for free nodes
+    height: i32,
+    entity: Option<Entity>,
+}
+
+/// Dynamic bounding volume tree over the bodies' `Aabb`s.
+///
+/// Leaves store a box grown by `margin`, so a body only has to be re-inserted once it leaves
+/// it. Insertion picks the sibling that grows the tree the least and rotations keep it balanced.
+/// `SpatialQuery` searches it together with the `StaticAabbTree` for game code.
+#[derive(Debug)]
+pub struct AabbTree {
+    nodes: Vec<TreeNode>,
+    root: usize,
+    free: Vec<usize>,
+    leaves: HashMap<Entity, usize>,
+    pub margin: f32,
+}
+
+impl Default for AabbTree {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            root: NULL_NODE,
+            free: Vec::new(),
+            leaves: Default::default(),
+            margin: 0.1,
+        }
+    }
+}
+
+impl AabbTree {
+    /// Updates the tree with the current bounding boxes. Entities that are no longer given are
+    /// removed, new ones are inserted.
+    pub fn update(&mut self, aabbs: impl Iterator<Item = (Entity, Aabb)>) {
+        let mut stale: HashMap<Entity, bool> = self.leaves.keys().map(|&entity| (entity, true)).collect();
+        for (entity, aabb) in aabbs {
+            stale.insert(entity, false);
+            self.set(entity, aabb);
+        }
+        // Removal order changes the shape of the tree, keep it deterministic
+        let mut stale: Vec<_> = stale.into_iter().filter(|(_, stale)| *stale).map(|(entity, _)| entity).collect();
+        stale.sort();
+        for entity in stale {
+            self.remove(entity);
+        }
+    }
+
+    /// Inserts `entity`, or moves it if it is already in the tree
+    pub fn set(&mut self, entity: Entity, aabb: Aabb) {
+        if let Some(&leaf) = self.leaves.get(&entity) {
+            self.nodes[leaf].tight = aabb;
+            if self.nodes[leaf].aabb.contains(&aabb) {
+                return;
+            }
+            self.remove_leaf(leaf);
+            self.nodes[leaf].aabb = aabb.inflated(self.margin);
+            self.insert_leaf(leaf);
+        } else {
+            let leaf = self.allocate(TreeNode {
+                aabb: aabb.inflated(self.margin),
+                tight: aabb,
+                parent: NULL_NODE,
+                children: [NULL_NODE; 2],
+                height: 0,
+                entity: Some(entity),
+            });
+            self.leaves.insert(entity, leaf);
+            self.insert_leaf(leaf);
+        }
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(leaf) = self.leaves.remove(&entity) {
+            self.remove_leaf(leaf);
+            self.nodes[leaf].height = -1;
+            self.nodes[leaf].entity = None;
+            self.free.push(leaf);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Entities whose bounding box contains `point`
+    pub fn point(&self, point: Vec2) -> Vec<Entity> {
+        let mut entities = Vec::new();
+        self.query(|aabb| aabb.contains_point(point), |entity, _| entities.push(entity));
+        entities
+    }
+
+    /// Entities whose bounding box overlaps `region`
+    pub fn region(&self, region: &Aabb) -> Vec<Entity> {
+        let mut entities = Vec::new();
+        self.query(|aabb| aabb.intersects(region), |entity, _| entities.push(entity));
+        entities
+    }
+
+    /// Entities whose bounding box the ray hits within `max_distance`, nearest first, with the
+    /// distance at which the ray enters the box. `direction` must be normalized. Boxes of bodies
+    /// that diverged to NaN or infinity are never hit.
+    pub fn ray(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> Vec<(Entity, f32)> {
+        let mut hits = Vec::new();
+        self.query(
+            |aabb| aabb.ray_distance(origin, direction, max_distance).is_some(),
+            |entity, aabb| {
+                let t = aabb.ray_distance(origin, direction, max_distance).filter(|t| t.is_finite());
+                hits.extend(t.map(|t| (entity, t)));
+            },
+        );
+        hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        hits
+    }
+
+    /// Pushes every pair of overlapping bounding boxes, the larger entity first
+    pub(crate) fn collect_pairs(&self, pairs: &mut Vec<(Entity, Entity)>) {
+        for node in self.nodes.iter() {
+            if let Some(entity_a) = node.entity {
+                self.query(
+                    |aabb| aabb.intersects(&node.tight),
+                    |entity_b, _| {
+                        if entity_a > entity_b {
+                            pairs.push((entity_a, entity_b));
+                        }
+                    },
+                );
+            }
+        }
+    }
+
+    /// Calls `f` for the leaves whose body's box passes `test`, pruning subtrees that fail it
+    fn query(&self, test: impl Fn(&Aabb) -> bool, mut f: impl FnMut(Entity, &Aabb)) {
+        if self.root == NULL_NODE {
+            return;
+        }
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !test(&node.aabb) {
+                continue;
+            }
+            match node.entity {
+                Some(entity) => {
+                    if test(&node.tight) {
+                        f(entity, &node.tight);
+                    }
+                }
+                None => stack.extend(node.children),
+            }
+        }
+    }
+
+    fn allocate(&mut self, node: TreeNode) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn is_leaf(&self, index: usize) -> bool {
+        self.nodes[index].children[0] == NULL_NODE
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        if self.root == NULL_NODE {
+            self.root = leaf;
+            self.nodes[leaf].parent = NULL_NODE;
+            return;
+        }
+
+        // Descend towards the sibling that makes the tree grow the least
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut index = self.root;
+        while !self.is_leaf(index) {
+            let perimeter = self.nodes[index].aabb.perimeter();
+            let combined = self.nodes[index].aabb.union(&leaf_aabb).perimeter();
+            // Cost of pairing the leaf with this node, and of pushing it further down
+            let cost = 2. * combined;
+            let inheritance = 2. * (combined - perimeter);
+            let child_cost = |child: usize| {
+                let union = leaf_aabb.union(&self.nodes[child].aabb).perimeter();
+                if self.is_leaf(child) {
+                    union + inheritance
+                } else {
+                    union - self.nodes[child].aabb.perimeter() + inheritance
+                }
+            };
+            let [child_1, child_2] = self.nodes[index].children;
+            let (cost_1, cost_2) = (child_cost(child_1), child_cost(child_2));
+            if cost < cost_1 && cost < cost_2 {
+                break;
+            }
+            index = if cost_1 < cost_2 { child_1 } else { child_2 };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate(TreeNode {
+            aabb: leaf_aabb.union(&self.nodes[sibling].aabb),
+            tight: Aabb::default(),
+            parent: old_parent,
+            children: [sibling, leaf],
+            height: self.nodes[sibling].height + 1,
+            entity: None,
+        });
+        self.nodes[sibling].parent = new_parent;
+        self.nodes[leaf].parent = new_parent;
+        self.replace_child(old_parent, sibling, new_parent);
+        self.refit(new_parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if leaf == self.root {
+            self.root = NULL_NODE;
+            return;
+        }
+        let parent = self.nodes[leaf].parent;
+        let grand_parent = self.nodes[parent].parent;
+        let [child_1, child_2] = self.nodes[parent].children;
+        let sibling = if child_1 == leaf { child_2 } else { child_1 };
+
+        self.nodes[sibling].parent = grand_parent;
+        self.replace_child(grand_parent, parent, sibling);
+        self.nodes[parent].height = -1;
+        self.free.push(parent);
+        if grand_parent != NULL_NODE {
+            self.refit(grand_parent);
+        }
+    }
+
+    /// Makes `new` take the place of `old` below `parent`, or at the root
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if parent == NULL_NODE {
+            self.root = new;
+        } else if self.nodes[parent].children[0] == old {
+            self.nodes[parent].children[0] = new;
+        } else {
+            self.nodes[parent].children[1] = new;
+        }
+    }
+
+    /// Rebalances and recomputes the boxes and heights from `index` up to the root
+    fn refit(&mut self, mut index: usize) {
+        while index != NULL_NODE {
+            index = self.balance(index);
+            self.fix(index);
+            index = self.nodes[index].parent;
+        }
+    }
+
+    fn fix(&mut self, index: usize) {
+        let [child_1, child_2] = self.nodes[index].children;
+        self.nodes[index].aabb = self.nodes[child_1].aabb.union(&self.nodes[child_2].aabb);
+        self.nodes[index].height = 1 + self.nodes[child_1].height.max(self.nodes[child_2].height);
+    }
+
+    /// Rotates the taller child of `index` up if the children's heights differ by more than one.
+    /// Returns the node now in its place.
+    fn balance(&mut self, index: usize) -> usize {
+        if self.is_leaf(index) || self.nodes[index].height < 2 {
+            return index;
+        }
+        let [child_1, child_2] = self.nodes[index].children;
+        let balance = self.nodes[child_2].height - self.nodes[child_1].height;
+        if balance > 1 {
+            self.rotate(index, 1)
+        } else if balance < -1 {
+            self.rotate(index, 0)
+        } else {
+            index
+        }
+    }
+
+    /// Moves the child in `slot` of `index` up into its place. The taller grandchild stays
+    /// below the promoted node, the other one goes to `index`.
+    fn rotate(&mut self, index: usize, slot: usize) -> usize {
+        let up = self.nodes[index].children[slot];
+        let [grand_child_1, grand_child_2] = self.nodes[up].children;
+        let parent = self.nodes[index].parent;
+
+        self.nodes[up].parent = parent;
+        self.nodes[index].parent = up;
+        self.replace_child(parent, index, up);
+
+        let (keep, give) = if self.nodes[grand_child_1].height > self.nodes[grand_child_2].height {
+            (grand_child_1, grand_child_2)
+        } else {
+            (grand_child_2, grand_child_1)
+        };
+        self.nodes[up].children = [index, keep];
+        self.nodes[index].children[slot] = give;
+        self.nodes[give].parent = index;
+        self.fix(index);
+        self.fix(up);
+        up
+    }
+}
+
+/// `AabbTree` over the bodies without `Mass`. They do not move on their own, so it has no fat
+/// margin and is only updated when a static body is added, moved or removed.
+#[derive(Debug)]
+pub struct StaticAabbTree(pub AabbTree);
+
+impl Default for StaticAabbTree {
+    fn default() -> Self {
+        Self(AabbTree {
+            margin: 0.,
+            ..Default::default()
+        })
+    }
+}
+
+/// Point, region and ray queries over the bounding boxes of all bodies, searching both the
+/// `AabbTree` of the moving bodies and the `StaticAabbTree`
+#[derive(SystemParam)]
+pub struct SpatialQuery<'w, 's> {
+    aabb_tree: Res<'w, AabbTree>,
+    static_aabb_tree: Res<'w, StaticAabbTree>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> SpatialQuery<'w, 's> {
+    /// Entities whose bounding box contains `point`
+    pub fn point(&self, point: Vec2) -> Vec<Entity> {
+        let mut entities = self.aabb_tree.point(point);
+        entities.extend(self.static_aabb_tree.0.point(point));
+        entities
+    }
+
+    /// Entities whose bounding box overlaps `region`
+    pub fn region(&self, region: &Aabb) -> Vec<Entity> {
+        let mut entities = self.aabb_tree.region(region);
+        entities.extend(self.static_aabb_tree.0.region(region));
+        entities
+    }
+
+    /// Entities whose bounding box the ray hits within `max_distance`, nearest first, see
+    /// `AabbTree::ray`
+    pub fn ray(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> Vec<(Entity, f32)> {
+        let mut hits = self.aabb_tree.ray(origin, direction, max_distance);
+        hits.extend(self.static_aabb_tree.0.ray(origin, direction, max_distance));
+        hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    fn random_aabbs(rng: &mut StdRng, n: u32) -> Vec<(Entity, Aabb)> {
+        (0..n)
+            .map(|i| {
+                let min = Vec2::new(rng.gen_range(-10.0..10.), rng.gen_range(-10.0..10.));
+                let size = Vec2::new(rng.gen_range(0.1..2.), rng.gen_range(0.1..2.));
+                (Entity::from_raw(i), Aabb { min, max: min + size })
+            })
+            .collect()
+    }
+
+    /// The pairs of the nested loop the broad phases replaced, in its order
+    fn brute_force(aabbs: &[(Entity, Aabb)]) -> Vec<(Entity, Entity)> {
+        let mut pairs = Vec::new();
+        for (entity_a, aabb_a) in aabbs {
+            for (entity_b, aabb_b) in aabbs {
+                if entity_a > entity_b && aabb_a.intersects(aabb_b) {
+                    pairs.push((*entity_a, *entity_b));
+                }
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn sweep_and_prune_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sweep_and_prune = SweepAndPrune::default();
+        let mut aabbs = random_aabbs(&mut rng, 200);
+        // Query order is not entity order
+        aabbs.shuffle(&mut rng);
+        for step in 0..5 {
+            // Move everything a little and drop a few entities between steps
+            for (_, aabb) in aabbs.iter_mut() {
+                let offset = Vec2::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5));
+                aabb.min += offset;
+                aabb.max += offset;
+            }
+            aabbs.retain(|(entity, _)| entity.id() % 7 != step);
+
+            sweep_and_prune.update(aabbs.iter().cloned());
+            let mut pairs = Vec::new();
+            sweep_and_prune.collect_pairs(&mut pairs);
+            assert_eq!(pairs, brute_force(&aabbs));
+        }
+    }
+
+    #[test]
+    fn sweep_and_prune_tall_stack() {
+        let mut sweep_and_prune = SweepAndPrune::default();
+        let mut aabbs: Vec<_> = (0..50)
+            .map(|i| {
+                let min = Vec2::new(0.01 * i as f32, 0.9 * i as f32);
+                (Entity::from_raw(i), Aabb { min, max: min + Vec2::ONE })
+            })
+            .collect();
+        sweep_and_prune.update(aabbs.iter().cloned());
+        assert_eq!(sweep_and_prune.axis, 1);
+        let mut pairs = Vec::new();
+        sweep_and_prune.collect_pairs(&mut pairs);
+        assert_eq!(pairs, brute_force(&aabbs));
+
+        // Many bodies added at once, spread out sideways
+        let mut rng = StdRng::seed_from_u64(1);
+        aabbs.extend(random_aabbs(&mut rng, 300).into_iter().map(|(entity, aabb)| {
+            let aabb = Aabb { min: aabb.min * Vec2::new(20., 1.), max: aabb.max * Vec2::new(20., 1.) };
+            (Entity::from_raw(entity.id() + 50), aabb)
+        }));
+        sweep_and_prune.update(aabbs.iter().cloned());
+        assert_eq!(sweep_and_prune.axis, 0);
+        let mut pairs = Vec::new();
+        sweep_and_prune.collect_pairs(&mut pairs);
+        assert_eq!(pairs, brute_force(&aabbs));
+    }
+
+    fn sorted_pairs(collect: impl FnOnce(&mut Vec<(Entity, Entity)>)) -> Vec<(Entity, Entity)> {
+        let mut pairs = Vec::new();
+        collect(&mut pairs);
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn aabb_tree_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut tree = AabbTree::default();
+        let mut aabbs = random_aabbs(&mut rng, 200);
+        for step in 0..5 {
+            for (_, aabb) in aabbs.iter_mut() {
+                let offset = Vec2::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5));
+                aabb.min += offset;
+                aabb.max += offset;
+            }
+            aabbs.retain(|(entity, _)| entity.id() % 7 != step);
+
+            tree.update(aabbs.iter().cloned());
+            assert_eq!(tree.len(), aabbs.len());
+            // Rotations keep the tree close to balanced
+            assert!(tree.nodes[tree.root].height < 2 * 8 + 1);
+            let mut expected = brute_force(&aabbs);
+            expected.sort();
+            assert_eq!(sorted_pairs(|pairs| tree.collect_pairs(pairs)), expected);
+        }
+    }
+
+    #[test]
+    fn aabb_tree_queries() {
+        let mut tree = AabbTree::default();
+        let entities: Vec<_> = (0..3).map(Entity::from_raw).collect();
+        tree.set(entities[0], Aabb::new(Vec2::new(0., 0.), Vec2::new(1., 1.)));
+        tree.set(entities[1], Aabb::new(Vec2::new(2., 0.), Vec2::new(3., 1.)));
+        tree.set(entities[2], Aabb::new(Vec2::new(0., 5.), Vec2::new(1., 6.)));
+
+        assert_eq!(tree.point(Vec2::new(0.5, 0.5)), vec![entities[0]]);
+        // Inside the fat margin but outside the body's own box
+        assert!(tree.point(Vec2::new(1.05, 0.5)).is_empty());
+
+        let mut region = tree.region(&Aabb::new(Vec2::new(0.5, 0.5), Vec2::new(2.5, 5.5)));
+        region.sort();
+        assert_eq!(region, entities);
+
+        let hits = tree.ray(Vec2::new(-1., 0.5), Vec2::X, 10.);
+        assert_eq!(hits.iter().map(|(entity, _)| *entity).collect::<Vec<_>>(), vec![entities[0], entities[1]]);
+        assert!((hits[0].1 - 1.).abs() < 0.0001);
+        assert!((hits[1].1 - 3.).abs() < 0.0001);
+        assert_eq!(tree.ray(Vec2::new(-1., 0.5), Vec2::X, 2.5).len(), 1);
+
+        tree.remove(entities[0]);
+        assert!(tree.point(Vec2::new(0.5, 0.5)).is_empty());
+
+        // A diverged body must not take the queries down with it
+        tree.set(entities[0], Aabb::new(Vec2::splat(f32::NAN), Vec2::splat(f32::INFINITY)));
+        let hits = tree.ray(Vec2::new(f32::NAN, 0.5), Vec2::X, f32::NAN);
+        assert!(hits.iter().all(|(_, t)| t.is_finite()));
+    }
+}
@@ -1,4 +1,11 @@
+use std::f32::consts::PI;
+
 use bevy::prelude::*;
+use parry2d::math::Point;
+use parry2d::shape::ConvexPolygon;
+use parry2d::transformation::convex_hull;
+
+use crate::Rot;
 
 #[derive(Component, Debug, Default)]
 pub struct Pos(pub Vec2);
@@ -6,9 +13,27 @@ pub struct Pos(pub Vec2);
 #[derive(Component, Debug, Default)]
 pub struct PrevPos(pub Vec2);
 
+/// Position at the start of the last physics step, see `PhysicsSettings::interpolate_transforms`
+#[derive(Component, Debug, Default)]
+pub struct PrevStepPos(pub Vec2);
+
 #[derive(Component, Debug, Default)]
 pub struct PreSolveAngVel(pub(crate) f32);
 
+/// A body moved by its `Vel` and `AngVel`, or towards a `KinematicTarget`, rather than by
+/// forces and contacts. Contacts treat it as infinitely heavy, but still see its velocity, so
+/// it carries and pushes dynamic bodies.
+#[derive(Component, Debug, Default)]
+pub struct Kinematic;
+
+/// Pose a `Kinematic` body moves to over the next step. Its velocities are derived from it
+/// every step, so it stops once it gets there.
+#[derive(Component, Debug, Default)]
+pub struct KinematicTarget {
+    pub pos: Vec2,
+    pub rot: Rot,
+}
+
 #[derive(Component, Debug)]
 pub struct Mass(pub f32);
 
@@ -18,11 +43,17 @@ impl Default for Mass {
     }
 }
 
-#[derive(Component, Debug)]
+#[derive(Debug, Clone)]
 pub struct CircleCollider {
     pub radius: f32,
 }
 
+impl CircleCollider {
+    pub fn inertia_inv_from_mass_inv(&self, mass_inv: f32) -> f32 {
+        2. * mass_inv / (self.radius * self.radius)
+    }
+}
+
 impl Default for CircleCollider {
     fn default() -> Self {
         Self { radius: 0.5 }
@@ -30,7 +61,7 @@ impl Default for CircleCollider {
 }
 
 
-#[derive(Component, Debug)]
+#[derive(Debug, Clone)]
 pub struct BoxCollider {
     pub size: Vec2,
 }
@@ -47,12 +78,434 @@ impl Default for BoxCollider {
     }
 }
 
+/// A capsule along the local y axis: a segment of length `2 * half_height` inflated by `radius`
+#[derive(Debug, Clone)]
+pub struct CapsuleCollider {
+    pub half_height: f32,
+    pub radius: f32,
+}
+
+impl CapsuleCollider {
+    pub fn inertia_inv_from_mass_inv(&self, mass_inv: f32) -> f32 {
+        let h = self.half_height;
+        let r = self.radius;
+        let rect_area = 4. * r * h;
+        let circle_area = PI * r * r;
+        let rect_fraction = rect_area / (rect_area + circle_area);
+        let circle_fraction = circle_area / (rect_area + circle_area);
+        // Each end cap is a half disc whose centroid sits 4r/3π past the end of the segment;
+        // move its inertia there, then out to the capsule's center (parallel-axis theorem)
+        let cap_centroid = 4. * r / (3. * PI);
+        let rect = rect_fraction * (r * r + h * h) / 3.;
+        let caps = circle_fraction * (r * r / 2. + h * h + 2. * h * cap_centroid);
+        mass_inv / (rect + caps)
+    }
+}
+
+impl Default for CapsuleCollider {
+    fn default() -> Self {
+        Self {
+            half_height: 0.5,
+            radius: 0.25,
+        }
+    }
+}
+
+/// A convex polygon built from the convex hull of a set of points.
+///
+/// The hull is re-centred on its centroid, so the body's `Pos` is its center of mass.
+#[derive(Debug, Clone)]
+pub struct ConvexPolygonCollider {
+    shape: ConvexPolygon,
+}
+
+impl ConvexPolygonCollider {
+    /// Returns `None` if the points do not enclose any area
+    pub fn from_points(points: &[Vec2]) -> Option<Self> {
+        let points: Vec<_> = points.iter().map(|p| Point::new(p.x, p.y)).collect();
+        let hull: Vec<_> = convex_hull(&points).iter().map(|p| Vec2::new(p.x, p.y)).collect();
+        let (area, centroid) = polygon_area_and_centroid(&hull);
+        if area <= f32::EPSILON {
+            return None;
+        }
+        let centred = hull
+            .iter()
+            .map(|p| Point::new(p.x - centroid.x, p.y - centroid.y))
+            .collect();
+        ConvexPolygon::from_convex_polyline(centred).map(|shape| Self { shape })
+    }
+
+    /// Vertices in counter-clockwise order, relative to the center of mass
+    pub fn vertices(&self) -> Vec<Vec2> {
+        self.shape.points().iter().map(|p| Vec2::new(p.x, p.y)).collect()
+    }
+
+    pub fn area(&self) -> f32 {
+        polygon_area_and_centroid(&self.vertices()).0
+    }
+
+    pub fn inertia_inv_from_mass_inv(&self, mass_inv: f32) -> f32 {
+        // Polar moment of inertia of a uniform polygon about its centroid, per unit mass
+        let vertices = self.vertices();
+        let mut numerator = 0.;
+        let mut denominator = 0.;
+        for (i, a) in vertices.iter().enumerate() {
+            let b = vertices[(i + 1) % vertices.len()];
+            let cross = a.perp_dot(b);
+            numerator += cross * (a.dot(*a) + a.dot(b) + b.dot(b));
+            denominator += cross;
+        }
+        6. * denominator * mass_inv / numerator
+    }
+
+    pub(crate) fn shape(&self) -> &ConvexPolygon {
+        &self.shape
+    }
+}
+
+/// Signed area and centroid of a simple polygon with counter-clockwise vertices
+fn polygon_area_and_centroid(vertices: &[Vec2]) -> (f32, Vec2) {
+    let mut area = 0.;
+    let mut centroid = Vec2::ZERO;
+    for (i, a) in vertices.iter().enumerate() {
+        let b = vertices[(i + 1) % vertices.len()];
+        let cross = a.perp_dot(b);
+        area += cross;
+        centroid += (*a + b) * cross;
+    }
+    area /= 2.;
+    if area.abs() > f32::EPSILON {
+        centroid /= 6. * area;
+    }
+    (area, centroid)
+}
+
+/// A chain of segments through `points`, meant for static terrain.
+///
+/// Contacts at the joints between segments are smoothed out, so bodies slide across them
+/// without catching on the seams.
+#[derive(Debug, Clone, Default)]
+pub struct PolylineCollider {
+    pub points: Vec<Vec2>,
+}
+
+impl PolylineCollider {
+    /// A polyline has no area, so it behaves as if its inertia were infinite
+    pub fn inertia_inv_from_mass_inv(&self, _mass_inv: f32) -> f32 {
+        0.
+    }
+}
+
+/// One shape of a compound body, placed relative to the body's center of mass
+#[derive(Debug, Clone)]
+pub struct CompoundPart {
+    pub pos: Vec2,
+    pub rot: Rot,
+    pub collider: Collider,
+    pub mass: f32,
+}
+
+/// Several shapes moving as one rigid body.
+///
+/// The plugin builds it from the children of the body that carry a `ChildCollider`, see
+/// `ChildColliderBundle`, and keeps the body's `Pos` at their combined center of mass.
+#[derive(Debug, Clone, Default)]
+pub struct CompoundCollider {
+    pub parts: Vec<CompoundPart>,
+}
+
+impl CompoundCollider {
+    pub fn mass(&self) -> f32 {
+        self.parts.iter().map(|part| part.mass).sum()
+    }
+
+    /// Center of mass of the parts, relative to the body's `Pos`
+    pub fn center_of_mass(&self) -> Vec2 {
+        let mass = self.mass();
+        if mass <= 0. {
+            return Vec2::ZERO;
+        }
+        self.parts.iter().fold(Vec2::ZERO, |sum, part| sum + part.pos * part.mass) / mass
+    }
+
+    /// Inverse inertia about the body's `Pos`, with the part masses scaled to `1 / mass_inv`
+    pub fn inertia_inv_from_mass_inv(&self, mass_inv: f32) -> f32 {
+        let mut inertia = 0.;
+        for part in &self.parts {
+            // Parallel-axis theorem: the part's own inertia plus its mass at its offset
+            let own_inv = part.collider.inertia_inv_from_mass_inv(1. / part.mass);
+            let own = if own_inv > 0. { 1. / own_inv } else { 0. };
+            inertia += own + part.mass * part.pos.length_squared();
+        }
+        if inertia <= 0. {
+            return 0.;
+        }
+        self.mass() * mass_inv / inertia
+    }
+}
+
+/// Marks a child entity whose `Collider` is a part of its parent's `CompoundCollider`.
+/// Its `Pos` and `Rot` are relative to the parent.
+#[derive(Component, Debug, Default)]
+pub struct ChildCollider;
+
+/// The shape of a body, used by the narrow phase to find contacts for any pair of shapes
+#[derive(Component, Debug, Clone)]
+pub enum Collider {
+    Circle(CircleCollider),
+    Box(BoxCollider),
+    ConvexPolygon(ConvexPolygonCollider),
+    Capsule(CapsuleCollider),
+    Polyline(PolylineCollider),
+    Compound(CompoundCollider),
+}
+
+impl Collider {
+    pub fn inertia_inv_from_mass_inv(&self, mass_inv: f32) -> f32 {
+        match self {
+            Collider::Circle(circle) => circle.inertia_inv_from_mass_inv(mass_inv),
+            Collider::Box(r#box) => r#box.inertia_inv_from_mass_inv(mass_inv),
+            Collider::ConvexPolygon(polygon) => polygon.inertia_inv_from_mass_inv(mass_inv),
+            Collider::Capsule(capsule) => capsule.inertia_inv_from_mass_inv(mass_inv),
+            Collider::Polyline(polyline) => polyline.inertia_inv_from_mass_inv(mass_inv),
+            Collider::Compound(compound) => compound.inertia_inv_from_mass_inv(mass_inv),
+        }
+    }
+
+    /// Tight bounding box of the shape placed at `pos` with rotation `rot`
+    pub fn aabb(&self, pos: Vec2, rot: Rot) -> Aabb {
+        let half_extents = match self {
+            Collider::Circle(circle) => Vec2::splat(circle.radius),
+            Collider::Box(r#box) => {
+                let sin = rot.sin().abs();
+                let cos = rot.cos().abs();
+                let box_w = r#box.size.x;
+                let box_h = r#box.size.y;
+                let w = box_h * sin + box_w * cos;
+                let h = box_w * sin + box_h * cos;
+                Vec2::new(w / 2., h / 2.)
+            }
+            Collider::ConvexPolygon(polygon) => return Aabb::from_vertices(&polygon.vertices(), pos, rot),
+            Collider::Polyline(polyline) => return Aabb::from_vertices(&polyline.points, pos, rot),
+            Collider::Capsule(capsule) => {
+                rot.rotate(Vec2::new(0., capsule.half_height)).abs() + Vec2::splat(capsule.radius)
+            }
+            Collider::Compound(compound) => {
+                return compound
+                    .parts
+                    .iter()
+                    .map(|part| part.collider.aabb(pos + rot.rotate(part.pos), rot.mul(part.rot)))
+                    .reduce(|a, b| a.union(&b))
+                    .unwrap_or(Aabb { min: pos, max: pos });
+            }
+        };
+        Aabb {
+            min: pos - half_extents,
+            max: pos + half_extents,
+        }
+    }
+}
+
+impl From<CircleCollider> for Collider {
+    fn from(circle: CircleCollider) -> Self {
+        Collider::Circle(circle)
+    }
+}
+
+impl From<BoxCollider> for Collider {
+    fn from(r#box: BoxCollider) -> Self {
+        Collider::Box(r#box)
+    }
+}
+
+impl From<CapsuleCollider> for Collider {
+    fn from(capsule: CapsuleCollider) -> Self {
+        Collider::Capsule(capsule)
+    }
+}
+
+impl From<PolylineCollider> for Collider {
+    fn from(polyline: PolylineCollider) -> Self {
+        Collider::Polyline(polyline)
+    }
+}
+
+impl From<ConvexPolygonCollider> for Collider {
+    fn from(polygon: ConvexPolygonCollider) -> Self {
+        Collider::ConvexPolygon(polygon)
+    }
+}
+
+impl From<CompoundCollider> for Collider {
+    fn from(compound: CompoundCollider) -> Self {
+        Collider::Compound(compound)
+    }
+}
+
 #[derive(Component, Debug, Default)]
 pub struct Vel(pub(crate) Vec2);
 
+impl Vel {
+    pub fn new(vel: Vec2) -> Self {
+        Self(vel)
+    }
+
+    pub fn get(&self) -> Vec2 {
+        self.0
+    }
+
+    /// Takes effect from the next step on
+    pub fn set(&mut self, vel: Vec2) {
+        self.0 = vel;
+    }
+}
+
 #[derive(Component, Debug, Default)]
 pub struct PreSolveVel(pub(crate) Vec2);
 
+/// Force applied at the center of mass in every substep. Unless `persistent`, it is reset to
+/// zero at the end of the step. A non-zero persistent force keeps its body from sleeping.
+#[derive(Component, Debug, Default, Clone)]
+pub struct ExternalForce {
+    pub force: Vec2,
+    pub persistent: bool,
+}
+
+impl ExternalForce {
+    pub fn new(force: Vec2) -> Self {
+        Self { force, persistent: false }
+    }
+
+    pub fn persistent(force: Vec2) -> Self {
+        Self { force, persistent: true }
+    }
+}
+
+/// Torque applied in every substep. Unless `persistent`, it is reset to zero at the end of the
+/// step. A non-zero persistent torque keeps its body from sleeping.
+#[derive(Component, Debug, Default, Clone)]
+pub struct ExternalTorque {
+    pub torque: f32,
+    pub persistent: bool,
+}
+
+impl ExternalTorque {
+    pub fn new(torque: f32) -> Self {
+        Self { torque, persistent: false }
+    }
+
+    pub fn persistent(torque: f32) -> Self {
+        Self { torque, persistent: true }
+    }
+}
+
+/// Instant change of momentum, applied at the start of the next step. Unless `persistent`, it
+/// is reset to zero once applied.
+#[derive(Component, Debug, Default, Clone)]
+pub struct ExternalImpulse {
+    pub impulse: Vec2,
+    pub angular_impulse: f32,
+    pub persistent: bool,
+}
+
+impl ExternalImpulse {
+    pub fn new(impulse: Vec2) -> Self {
+        Self {
+            impulse,
+            ..Default::default()
+        }
+    }
+
+    /// Impulse applied at `point`, which also spins the body around its center of mass `pos`
+    pub fn at_point(impulse: Vec2, point: Vec2, pos: Vec2) -> Self {
+        Self {
+            impulse,
+            angular_impulse: (point - pos).perp_dot(impulse),
+            ..Default::default()
+        }
+    }
+}
+
+/// Rate at which a body loses linear velocity, per second. Overrides
+/// `PhysicsSettings::linear_damping`.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct LinearDamping(pub f32);
+
+/// Rate at which a body loses angular velocity, per second. Overrides
+/// `PhysicsSettings::angular_damping`.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct AngularDamping(pub f32);
+
+/// Marks a body that was put to sleep together with its contact island. It is not integrated
+/// or solved and acts as infinitely heavy until something wakes it.
+#[derive(Component, Debug, Default)]
+pub struct Sleeping;
+
+/// Speeds below which a body counts as resting, see `PhysicsSettings::time_to_sleep`.
+/// Overrides `PhysicsSettings::sleep_threshold`.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct SleepThreshold {
+    pub linear: f32,
+    pub angular: f32,
+}
+
+impl SleepThreshold {
+    /// Keeps the body, and everything touching it, awake
+    pub const NEVER: Self = Self {
+        linear: -1.,
+        angular: -1.,
+    };
+}
+
+impl Default for SleepThreshold {
+    fn default() -> Self {
+        Self {
+            linear: 0.05,
+            angular: 0.05,
+        }
+    }
+}
+
+/// How long a body has been resting, in seconds
+#[derive(Component, Debug, Default)]
+pub struct SleepTimer(pub(crate) f32);
+
+/// Multiplies all gravity acting on a body: the global `Gravity`, attractors and zones.
+/// Zero makes it float.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct GravityScale(pub f32);
+
+impl Default for GravityScale {
+    fn default() -> Self {
+        Self(1.)
+    }
+}
+
+/// Pulls bodies towards the entity's `Pos` with an acceleration of `strength / distance²`.
+/// Closer than `min_distance`, for example inside a planet, the pull stops growing.
+#[derive(Component, Debug, Clone)]
+pub struct GravityAttractor {
+    pub strength: f32,
+    pub min_distance: f32,
+}
+
+impl Default for GravityAttractor {
+    fn default() -> Self {
+        Self {
+            strength: 10.,
+            min_distance: 1.,
+        }
+    }
+}
+
+/// Adds `gravity` to bodies whose center is inside a rectangle of `size`, centred on the
+/// entity's `Pos` and turned by its `Rot` if it has one
+#[derive(Component, Debug, Clone, Default)]
+pub struct GravityZone {
+    pub size: Vec2,
+    pub gravity: Vec2,
+}
+
 #[derive(Component, Debug)]
 pub struct Restitution(pub f32);
 
@@ -62,13 +515,109 @@ impl Default for Restitution {
     }
 }
 
-#[derive(Component, Debug, Default)]
+#[derive(Component, Debug)]
+pub struct Friction {
+    pub static_coefficient: f32,
+    pub dynamic_coefficient: f32,
+}
+
+impl Default for Friction {
+    fn default() -> Self {
+        Self {
+            static_coefficient: 0.5,
+            dynamic_coefficient: 0.3,
+        }
+    }
+}
+
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
 pub struct Aabb {
     pub(crate) min: Vec2,
     pub(crate) max: Vec2,
 }
 
 impl Aabb {
+    pub fn new(min: Vec2, max: Vec2) -> Self {
+        Self { min, max }
+    }
+
+    pub fn min(&self) -> Vec2 {
+        self.min
+    }
+
+    pub fn max(&self) -> Vec2 {
+        self.max
+    }
+
+    /// Bounding box of local space `vertices` placed at `pos` with rotation `rot`
+    fn from_vertices(vertices: &[Vec2], pos: Vec2, rot: Rot) -> Self {
+        let mut aabb = Aabb {
+            min: Vec2::splat(f32::MAX),
+            max: Vec2::splat(f32::MIN),
+        };
+        for &vertex in vertices {
+            let vertex = pos + rot.rotate(vertex);
+            aabb.min = aabb.min.min(vertex);
+            aabb.max = aabb.max.max(vertex);
+        }
+        aabb
+    }
+
+    pub(crate) fn union(&self, other: &Self) -> Self {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// The same box grown by `margin` on every side
+    pub(crate) fn inflated(&self, margin: f32) -> Self {
+        Aabb {
+            min: self.min - Vec2::splat(margin),
+            max: self.max + Vec2::splat(margin),
+        }
+    }
+
+    pub(crate) fn perimeter(&self) -> f32 {
+        let size = self.max - self.min;
+        2. * (size.x + size.y)
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && self.max.x >= other.max.x
+            && self.max.y >= other.max.y
+    }
+
+    pub fn contains_point(&self, point: Vec2) -> bool {
+        self.min.x <= point.x && self.min.y <= point.y && self.max.x >= point.x && self.max.y >= point.y
+    }
+
+    /// Distance along the ray at which it enters the box, or `None` if it misses it within
+    /// `max_distance`. `direction` must be normalized.
+    pub fn ray_distance(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> Option<f32> {
+        let mut enter = 0f32;
+        let mut exit = max_distance;
+        for axis in 0..2 {
+            if direction[axis].abs() <= f32::EPSILON {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1. / direction[axis];
+            let t1 = (self.min[axis] - origin[axis]) * inv;
+            let t2 = (self.max[axis] - origin[axis]) * inv;
+            enter = enter.max(t1.min(t2));
+            exit = exit.min(t1.max(t2));
+            if enter > exit {
+                return None;
+            }
+        }
+        Some(enter)
+    }
+
     pub fn intersects(&self, other: &Self) -> bool {
         self.max.x >= other.min.x
             && self.max.y >= other.min.y
@@ -1,7 +1,8 @@
 use bevy::prelude::*;
 use nalgebra::UnitComplex;
-use parry2d::shape::Cuboid;
-use crate::Rot;
+use parry2d::math::Point;
+use parry2d::shape::{Ball, Capsule, Cuboid, Segment, Shape};
+use crate::{Collider, PolylineCollider, Rot};
 
 #[derive(Debug, PartialEq)]
 pub struct Contact {
@@ -11,6 +12,92 @@ pub struct Contact {
     pub r_b: Vec2,
 }
 
+impl Contact {
+    /// The same contact as seen from the other body
+    pub fn flipped(self) -> Self {
+        Self {
+            penetration: self.penetration,
+            normal: -self.normal,
+            r_a: self.r_b,
+            r_b: self.r_a,
+        }
+    }
+}
+
+/// Narrow phase: dispatches on the pair of shapes. The normal points from a towards b.
+pub fn contact(
+    pos_a: Vec2,
+    rot_a: Rot,
+    collider_a: &Collider,
+    pos_b: Vec2,
+    rot_b: Rot,
+    collider_b: &Collider,
+) -> Option<Contact> {
+    match (collider_a, collider_b) {
+        (Collider::Circle(a), Collider::Circle(b)) => ball_ball(pos_a, a.radius, pos_b, b.radius),
+        (Collider::Circle(a), Collider::Box(b)) => ball_box(pos_a, a.radius, pos_b, rot_b, b.size),
+        (Collider::Box(_), Collider::Circle(_)) => {
+            contact(pos_b, rot_b, collider_b, pos_a, rot_a, collider_a).map(Contact::flipped)
+        }
+        (Collider::Box(a), Collider::Box(b)) => box_box(pos_a, rot_a, a.size, pos_b, rot_b, b.size),
+        (Collider::Compound(_), _) | (_, Collider::Compound(_)) => {
+            let mut deepest: Option<Contact> = None;
+            for_each_contact(pos_a, rot_a, collider_a, pos_b, rot_b, collider_b, &mut |contact| {
+                if deepest.as_ref().is_none_or(|deepest| contact.penetration > deepest.penetration) {
+                    deepest = Some(contact);
+                }
+            });
+            deepest
+        }
+        (Collider::Polyline(_), Collider::Polyline(_)) => None,
+        (Collider::Polyline(_), _) => {
+            contact(pos_b, rot_b, collider_b, pos_a, rot_a, collider_a).map(Contact::flipped)
+        }
+        (_, Collider::Polyline(b)) => collider_polyline(pos_a, rot_a, collider_a, pos_b, rot_b, b),
+        _ => with_shape(collider_a, |shape_a| {
+            with_shape(collider_b, |shape_b| shape_shape(pos_a, rot_a, shape_a, pos_b, rot_b, shape_b))
+        }),
+    }
+}
+
+/// Calls `f` with every contact between two colliders: one per pair of parts for compound
+/// shapes, with the arms measured from the compound body's center of mass
+pub fn for_each_contact(
+    pos_a: Vec2,
+    rot_a: Rot,
+    collider_a: &Collider,
+    pos_b: Vec2,
+    rot_b: Rot,
+    collider_b: &Collider,
+    f: &mut dyn FnMut(Contact),
+) {
+    match (collider_a, collider_b) {
+        (Collider::Compound(a), _) => {
+            for part in &a.parts {
+                let offset = rot_a.rotate(part.pos);
+                for_each_contact(pos_a + offset, rot_a.mul(part.rot), &part.collider, pos_b, rot_b, collider_b,
+                                 &mut |contact| f(Contact { r_a: contact.r_a + offset, ..contact }));
+            }
+        }
+        (_, Collider::Compound(b)) => {
+            for part in &b.parts {
+                let offset = rot_b.rotate(part.pos);
+                for_each_contact(pos_a, rot_a, collider_a, pos_b + offset, rot_b.mul(part.rot), &part.collider,
+                                 &mut |contact| f(Contact { r_b: contact.r_b + offset, ..contact }));
+            }
+        }
+        (Collider::Polyline(_), Collider::Polyline(_)) => {}
+        (Collider::Polyline(a), _) => {
+            for_each_polyline_contact(pos_b, rot_b, collider_b, pos_a, rot_a, a, &mut |contact| f(contact.flipped()));
+        }
+        (_, Collider::Polyline(b)) => for_each_polyline_contact(pos_a, rot_a, collider_a, pos_b, rot_b, b, f),
+        _ => {
+            if let Some(contact) = contact(pos_a, rot_a, collider_a, pos_b, rot_b, collider_b) {
+                f(contact);
+            }
+        }
+    }
+}
 
 fn make_isometry(rotation: Rot, translation: Vec2) -> parry2d::math::Isometry<f32> {
     parry2d::math::Isometry::<f32> {
@@ -30,16 +117,17 @@ pub fn ball_ball(pos_a: Vec2, radius_a: f32, pos_b: Vec2, radius_b: f32) -> Opti
         Some(Contact {
             normal,
             penetration,
-            r_a: todo!(),
-            r_b: todo!(),
+            r_a: normal * radius_a,
+            r_b: -normal * radius_b,
         })
     } else {
         None
     }
 }
 
-pub fn ball_box(pos_a: Vec2, radius_a: f32, pos_b: Vec2, size_b: Vec2) -> Option<Contact> {
-    let box_to_circle = pos_a - pos_b;
+/// Circle-box contact for a box of any orientation, computed in the box's local frame
+pub fn ball_box(pos_a: Vec2, radius_a: f32, pos_b: Vec2, rot_b: Rot, size_b: Vec2) -> Option<Contact> {
+    let box_to_circle = rot_b.inv().rotate(pos_a - pos_b);
     let box_to_circle_abs = box_to_circle.abs();
     let half_extents = size_b / 2.;
     let corner_to_center = box_to_circle_abs - half_extents;
@@ -68,78 +156,10 @@ pub fn ball_box(pos_a: Vec2, radius_a: f32, pos_b: Vec2, size_b: Vec2) -> Option
     };
 
     Some(Contact {
-        normal: n,
+        normal: rot_b.rotate(n),
         penetration,
-        r_a: todo!(),
-        r_b: todo!(),
-    })
-}
-
-fn local_box_box(half_a: Vec2, ab: Vec2, rot_b: Rot, half_b: Vec2) -> Option<Contact> {
-    let v1 = rot_b.rotate(Vec2::new(half_b.x, half_b.y));
-    let v2 = rot_b.rotate(Vec2::new(half_b.x, -half_b.y));
-    let v3 = -v1;
-    let v4 = -v2;
-
-    let v1 = v1 + ab;
-    let v2 = v2 + ab;
-    let v3 = v3 + ab;
-    let v4 = v4 + ab;
-
-    let mut min_penetration = f32::MAX;
-    let mut n = Vec2::ZERO;
-    let v_max = v1.max(v2).max(v3.max(v4));
-    let v_min = v1.min(v2).min(v3.min(v4));
-
-    // right edge
-    {
-        let penetration = half_a.x - v_min.x;
-        if penetration < 0. {
-            return None;
-        } else if penetration < min_penetration {
-            min_penetration = penetration;
-            n = Vec2::X;
-        }
-    }
-
-    // left edge
-    {
-        let penetration = half_a.x + v_max.x;
-        if penetration < 0. {
-            return None;
-        } else if penetration < min_penetration {
-            min_penetration = penetration;
-            n = -Vec2::X;
-        }
-    }
-
-    // top edge
-    {
-        let penetration = half_a.y - v_min.y;
-        if penetration < 0. {
-            return None;
-        } else if penetration < min_penetration {
-            min_penetration = penetration;
-            n = Vec2::Y;
-        }
-    }
-
-    // bottom edge
-    {
-        let penetration = half_a.y + v_max.y;
-        if penetration < 0. {
-            return None;
-        } else if penetration < min_penetration {
-            min_penetration = penetration;
-            n = -Vec2::Y;
-        }
-    }
-
-    Some(Contact {
-        penetration: min_penetration,
-        normal: n,
-        r_a: todo!(),
-        r_b: todo!(),
+        r_a: rot_b.rotate(n * r),
+        r_b: rot_b.rotate(box_to_circle + n * (r - penetration)),
     })
 }
 
@@ -151,11 +171,23 @@ pub fn box_box(
     rot_b: Rot,
     size_b: Vec2,
 ) -> Option<Contact> {
-    let pos1 = make_isometry(rot_a, pos_a);
-    let pos2 = make_isometry(rot_b, pos_b);
     let cuboid1 = Cuboid::new((size_a / 2.).into());
     let cuboid2 = Cuboid::new((size_b / 2.).into());
-    let contact = parry2d::query::contact::contact(&pos1, &cuboid1, &pos2, &cuboid2, 0.0).unwrap();
+    shape_shape(pos_a, rot_a, &cuboid1, pos_b, rot_b, &cuboid2)
+}
+
+/// Contact between any two shapes supported by parry
+pub fn shape_shape(
+    pos_a: Vec2,
+    rot_a: Rot,
+    shape_a: &dyn Shape,
+    pos_b: Vec2,
+    rot_b: Rot,
+    shape_b: &dyn Shape,
+) -> Option<Contact> {
+    let pos1 = make_isometry(rot_a, pos_a);
+    let pos2 = make_isometry(rot_b, pos_b);
+    let contact = parry2d::query::contact::contact(&pos1, shape_a, &pos2, shape_b, 0.0).unwrap();
     match contact {
         Some(c) => {
             if c.dist > 0. {
@@ -173,9 +205,193 @@ pub fn box_box(
     }
 }
 
+/// Runs `f` with the parry equivalent of `collider`
+fn with_shape<R>(collider: &Collider, f: impl FnOnce(&dyn Shape) -> R) -> R {
+    match collider {
+        Collider::Circle(circle) => f(&Ball::new(circle.radius)),
+        Collider::Box(r#box) => f(&Cuboid::new((r#box.size / 2.).into())),
+        Collider::ConvexPolygon(polygon) => f(polygon.shape()),
+        Collider::Capsule(capsule) => f(&Capsule::new_y(capsule.half_height, capsule.radius)),
+        Collider::Polyline(_) => unreachable!("polylines are tested segment by segment"),
+        Collider::Compound(_) => unreachable!("compounds are tested part by part"),
+    }
+}
+
+/// How far (as `1 - cos(angle)`) a normal may stray from a segment's face normal and still
+/// count as a face contact rather than a contact with one of the segment's end points
+const FACE_NORMAL_TOLERANCE: f32 = 1e-4;
+
+/// Contact between any shape and a polyline, taking the deepest contact over all segments
+pub fn collider_polyline(
+    pos_a: Vec2,
+    rot_a: Rot,
+    collider_a: &Collider,
+    pos_b: Vec2,
+    rot_b: Rot,
+    polyline: &PolylineCollider,
+) -> Option<Contact> {
+    let mut deepest: Option<Contact> = None;
+    for_each_polyline_contact(pos_a, rot_a, collider_a, pos_b, rot_b, polyline, &mut |contact| {
+        if deepest.as_ref().is_none_or(|deepest| contact.penetration > deepest.penetration) {
+            deepest = Some(contact);
+        }
+    });
+    deepest
+}
+
+/// Calls `f` with the contact of every segment of a polyline that touches the shape, so that a
+/// body resting across several segments is supported by all of them.
+///
+/// A segment's end point is shared with its neighbour, so a contact there whose normal does not
+/// lie in the joint's normal cone is a "ghost" produced by the seam. Such contacts are replaced by
+/// a contact along the segment's face normal, which is what lets bodies slide over the joints.
+/// The face contact is dropped when it lies beyond the segment's ends, where the neighbour
+/// segment is responsible for it.
+pub fn for_each_polyline_contact(
+    pos_a: Vec2,
+    rot_a: Rot,
+    collider_a: &Collider,
+    pos_b: Vec2,
+    rot_b: Rot,
+    polyline: &PolylineCollider,
+    f: &mut dyn FnMut(Contact),
+) {
+    // A repeated point makes a segment of zero length, which has no normal
+    let mut points = polyline.points.clone();
+    points.dedup();
+    for i in 0..points.len().saturating_sub(1) {
+        let (start, end) = (points[i], points[i + 1]);
+        let contact = match collider_a {
+            Collider::Circle(circle) => ball_segment(pos_a, circle.radius, pos_b, rot_b, start, end),
+            _ => with_shape(collider_a, |shape_a| {
+                let segment = Segment::new(Point::new(start.x, start.y), Point::new(end.x, end.y));
+                shape_shape(pos_a, rot_a, shape_a, pos_b, rot_b, &segment)
+            }),
+        };
+        let contact = match contact {
+            Some(contact) => contact,
+            None => continue,
+        };
+
+        // Everything below is in the polyline's local frame, with normals pointing towards a
+        let normal = rot_b.inv().rotate(-contact.normal);
+        let left = (end - start).perp().normalize();
+        let face_normal = if normal.dot(left) >= 0. { left } else { -left };
+
+        let contact = if normal.dot(face_normal) > 1. - FACE_NORMAL_TOLERANCE {
+            Some(contact)
+        } else {
+            let local_point = rot_b.inv().rotate(contact.r_b);
+            let at_start = local_point.distance_squared(start) < local_point.distance_squared(end);
+            let neighbour = if at_start {
+                i.checked_sub(1).map(|j| points[j])
+            } else {
+                points.get(i + 2).copied()
+            };
+            match neighbour {
+                // The open ends of the polyline are real corners
+                None => Some(contact),
+                Some(neighbour) => {
+                    let vertex = if at_start { start } else { end };
+                    let neighbour_dir = neighbour - vertex;
+                    // Same side of the chain as `face_normal`, following the polyline's direction
+                    let neighbour_left = if at_start { -neighbour_dir } else { neighbour_dir }.perp().normalize();
+                    let neighbour_normal = if left.dot(face_normal) > 0. {
+                        neighbour_left
+                    } else {
+                        -neighbour_left
+                    };
+                    // The joint only has a corner on a's side if the neighbour bends away from a
+                    let convex = neighbour_dir.normalize().dot(face_normal) < -FACE_NORMAL_TOLERANCE;
+                    if convex && in_normal_cone(normal, face_normal, neighbour_normal) {
+                        Some(contact)
+                    } else {
+                        let face_normal = rot_b.rotate(face_normal);
+                        with_shape(collider_a, |shape_a| {
+                            face_contact(pos_a, rot_a, shape_a, pos_b, rot_b, (start, end), face_normal)
+                        })
+                    }
+                }
+            }
+        };
+
+        if let Some(contact) = contact {
+            f(contact);
+        }
+    }
+}
+
+/// Contact between a circle and the segment from `start` to `end`, local to body b
+pub fn ball_segment(pos_a: Vec2, radius_a: f32, pos_b: Vec2, rot_b: Rot, start: Vec2, end: Vec2) -> Option<Contact> {
+    let center = rot_b.inv().rotate(pos_a - pos_b);
+    let segment = end - start;
+    let t = ((center - start).dot(segment) / segment.length_squared()).clamp(0., 1.);
+    let closest = start + segment * t;
+    let to_closest = closest - center;
+    let dist_sqr = to_closest.length_squared();
+    if dist_sqr >= radius_a * radius_a {
+        return None;
+    }
+    let dist = dist_sqr.sqrt();
+    let normal = if dist > f32::EPSILON {
+        to_closest / dist
+    } else {
+        -segment.perp().normalize()
+    };
+    Some(Contact {
+        penetration: radius_a - dist,
+        normal: rot_b.rotate(normal),
+        r_a: rot_b.rotate(normal * radius_a),
+        r_b: rot_b.rotate(closest),
+    })
+}
+
+/// Whether `n` lies between the unit normals `u` and `w`, which are less than half a turn apart
+fn in_normal_cone(n: Vec2, u: Vec2, w: Vec2) -> bool {
+    let turn = u.perp_dot(w);
+    u.perp_dot(n) * turn >= 0. && n.perp_dot(w) * turn >= 0.
+}
+
+/// Contact of a shape against the face of the segment `(start, end)` (local to b) with world
+/// space normal `face_normal` pointing towards a. There is none if the contact point on the face
+/// lies beyond the segment's ends.
+fn face_contact(
+    pos_a: Vec2,
+    rot_a: Rot,
+    shape_a: &dyn Shape,
+    pos_b: Vec2,
+    rot_b: Rot,
+    (start, end): (Vec2, Vec2),
+    face_normal: Vec2,
+) -> Option<Contact> {
+    let support_map = shape_a.as_support_map()?;
+    let deepest = support_map.support_point(&make_isometry(rot_a, pos_a), &(-face_normal).into());
+    let deepest = Vec2::new(deepest.x, deepest.y);
+    let penetration = (pos_b + rot_b.rotate(start) - deepest).dot(face_normal);
+    if penetration <= 0. {
+        return None;
+    }
+    let r_b = deepest + face_normal * penetration - pos_b;
+    let segment = end - start;
+    let t = (rot_b.inv().rotate(r_b) - start).dot(segment) / segment.length_squared();
+    if !(0. ..=1.).contains(&t) {
+        return None;
+    }
+    Some(Contact {
+        penetration,
+        normal: -face_normal,
+        r_a: deepest - pos_a,
+        r_b,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        BoxCollider, CapsuleCollider, CircleCollider, CompoundCollider, CompoundPart, ConvexPolygonCollider,
+        PolylineCollider,
+    };
 
     #[test]
     fn box_box_clear() {
@@ -204,8 +420,7 @@ mod tests {
         let Contact {
             normal,
             penetration,
-            r_a: todo!(),
-            r_b: todo!(),
+            ..
         } = box_box(Vec2::ZERO, Default::default(), Vec2::ONE,
                     Vec2::new(0.9, 0.), Default::default(), Vec2::ONE).unwrap();
 
@@ -213,4 +428,363 @@ mod tests {
         assert!(normal.y < 0.001);
         assert!((penetration - 0.1).abs() < 0.001);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ball_ball_contact_arms() {
+        let Contact {
+            normal,
+            penetration,
+            r_a,
+            r_b,
+        } = ball_ball(Vec2::ZERO, 0.5, Vec2::new(0.8, 0.), 0.5).unwrap();
+
+        assert!((normal - Vec2::X).length() < 0.001);
+        assert!((penetration - 0.2).abs() < 0.001);
+        assert!((r_a - Vec2::new(0.5, 0.)).length() < 0.001);
+        assert!((r_b - Vec2::new(-0.5, 0.)).length() < 0.001);
+    }
+
+    #[test]
+    fn ball_box_contact_arms() {
+        let Contact {
+            normal,
+            penetration,
+            r_a,
+            r_b,
+        } = ball_box(Vec2::new(0., 0.9), 0.5, Vec2::ZERO, Rot::ZERO, Vec2::ONE).unwrap();
+
+        assert!((normal + Vec2::Y).length() < 0.001);
+        assert!((penetration - 0.1).abs() < 0.001);
+        assert!((r_a - Vec2::new(0., -0.5)).length() < 0.001);
+        assert!((r_b - Vec2::new(0., 0.5)).length() < 0.001);
+    }
+
+    #[test]
+    fn ball_box_tilted_clear() {
+        // Would touch the corner of an axis-aligned box, but the tilted box's corner points away
+        assert!(ball_box(Vec2::new(0.55, 0.55), 0.1, Vec2::ZERO, Rot::ZERO, Vec2::ONE).is_some());
+        assert!(ball_box(Vec2::new(0.55, 0.55), 0.1, Vec2::ZERO, Rot::from_degrees(45.), Vec2::ONE).is_none());
+    }
+
+    #[test]
+    fn ball_box_tilted_corner() {
+        // The top corner of a box rotated by 45 degrees pokes up to y = sqrt(2) / 2
+        let Contact {
+            normal,
+            penetration,
+            r_a,
+            r_b,
+        } = ball_box(Vec2::new(0., 1.), 0.5, Vec2::ZERO, Rot::from_degrees(45.), Vec2::ONE).unwrap();
+
+        assert!((normal + Vec2::Y).length() < 0.001);
+        assert!((penetration - (0.5 - (1. - 0.5f32.sqrt()))).abs() < 0.001);
+        assert!((r_a - Vec2::new(0., -0.5)).length() < 0.001);
+        assert!((r_b - Vec2::new(0., 0.5f32.sqrt())).length() < 0.001);
+    }
+
+    #[test]
+    fn ball_box_tilted_face() {
+        // A circle resting on the upper face of a 30 degree ramp
+        let rot = Rot::from_degrees(30.);
+        let up = rot.rotate(Vec2::Y);
+        let Contact {
+            normal,
+            penetration,
+            r_a,
+            r_b,
+        } = ball_box(Vec2::new(1., 2.) + up * 0.9, 0.5, Vec2::new(1., 2.), rot, Vec2::ONE).unwrap();
+
+        assert!((normal + up).length() < 0.001);
+        assert!((penetration - 0.1).abs() < 0.001);
+        assert!((r_a + up * 0.5).length() < 0.001);
+        assert!((r_b - up * 0.5).length() < 0.001);
+    }
+
+    #[test]
+    fn contact_dispatch_flips_box_circle() {
+        let circle = Collider::Circle(CircleCollider { radius: 0.5 });
+        let r#box = Collider::Box(BoxCollider { size: Vec2::ONE });
+        let circle_box = contact(Vec2::new(0., 0.9), Rot::ZERO, &circle, Vec2::ZERO, Rot::ZERO, &r#box).unwrap();
+        let box_circle = contact(Vec2::ZERO, Rot::ZERO, &r#box, Vec2::new(0., 0.9), Rot::ZERO, &circle).unwrap();
+
+        assert_eq!(box_circle, circle_box.flipped());
+        assert!((box_circle.normal - Vec2::Y).length() < 0.001);
+    }
+
+    fn triangle() -> Collider {
+        // Centroid at the origin, apex pointing up
+        Collider::ConvexPolygon(
+            ConvexPolygonCollider::from_points(&[Vec2::new(-1., -1.), Vec2::new(1., -1.), Vec2::new(0., 2.)]).unwrap(),
+        )
+    }
+
+    #[test]
+    fn convex_polygon_hull() {
+        // Interior points are dropped and the hull is centred on its centroid
+        let polygon = ConvexPolygonCollider::from_points(&[
+            Vec2::new(1., 1.),
+            Vec2::new(3., 1.),
+            Vec2::new(2., 1.5),
+            Vec2::new(3., 3.),
+            Vec2::new(1., 3.),
+        ]).unwrap();
+
+        assert_eq!(polygon.vertices().len(), 4);
+        assert!(polygon.vertices().iter().all(|v| (v.abs() - Vec2::ONE).length() < 0.001));
+        assert!((polygon.area() - 4.).abs() < 0.001);
+    }
+
+    #[test]
+    fn convex_polygon_degenerate() {
+        assert!(ConvexPolygonCollider::from_points(&[Vec2::ZERO, Vec2::X, Vec2::X * 2.]).is_none());
+    }
+
+    #[test]
+    fn convex_polygon_inertia_matches_box() {
+        let polygon = ConvexPolygonCollider::from_points(&[
+            Vec2::new(-1., -0.5),
+            Vec2::new(1., -0.5),
+            Vec2::new(1., 0.5),
+            Vec2::new(-1., 0.5),
+        ]).unwrap();
+        let r#box = BoxCollider { size: Vec2::new(2., 1.) };
+
+        let expected = r#box.inertia_inv_from_mass_inv(0.5);
+        assert!((polygon.inertia_inv_from_mass_inv(0.5) - expected).abs() < 0.001);
+    }
+
+    #[test]
+    fn convex_polygon_ball() {
+        let circle = Collider::Circle(CircleCollider { radius: 0.5 });
+        assert!(contact(Vec2::new(0., 2.6), Rot::ZERO, &circle, Vec2::ZERO, Rot::ZERO, &triangle()).is_none());
+
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::new(0., 2.4), Rot::ZERO, &circle, Vec2::ZERO, Rot::ZERO, &triangle()).unwrap();
+        assert!((normal + Vec2::Y).length() < 0.001);
+        assert!((penetration - 0.1).abs() < 0.001);
+    }
+
+    #[test]
+    fn convex_polygon_box() {
+        let r#box = Collider::Box(BoxCollider { size: Vec2::ONE });
+        assert!(contact(Vec2::ZERO, Rot::ZERO, &triangle(), Vec2::new(0., -1.6), Rot::ZERO, &r#box).is_none());
+
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::ZERO, Rot::ZERO, &triangle(), Vec2::new(0., -1.4), Rot::ZERO, &r#box).unwrap();
+        assert!((normal + Vec2::Y).length() < 0.001);
+        assert!((penetration - 0.1).abs() < 0.001);
+    }
+
+    #[test]
+    fn convex_polygon_convex_polygon() {
+        // Two triangles side by side, the right one rotated half a turn
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::ZERO, Rot::ZERO, &triangle(), Vec2::new(1.2, 0.), Rot::from_degrees(180.), &triangle()).unwrap();
+        assert!(normal.x > 0.);
+        assert!(penetration > 0.);
+    }
+
+    fn capsule() -> Collider {
+        Collider::Capsule(CapsuleCollider {
+            half_height: 0.5,
+            radius: 0.25,
+        })
+    }
+
+    #[test]
+    fn capsule_inertia_without_segment_is_disc() {
+        let capsule = CapsuleCollider {
+            half_height: 0.,
+            radius: 0.5,
+        };
+        let circle = CircleCollider { radius: 0.5 };
+        assert!((capsule.inertia_inv_from_mass_inv(2.) - circle.inertia_inv_from_mass_inv(2.)).abs() < 0.001);
+    }
+
+    #[test]
+    fn capsule_aabb() {
+        let aabb = capsule().aabb(Vec2::new(1., 1.), Rot::ZERO);
+        assert!((aabb.min - Vec2::new(0.75, 0.25)).length() < 0.001);
+        assert!((aabb.max - Vec2::new(1.25, 1.75)).length() < 0.001);
+
+        let aabb = capsule().aabb(Vec2::ZERO, Rot::from_degrees(90.));
+        assert!((aabb.min - Vec2::new(-0.75, -0.25)).length() < 0.001);
+        assert!((aabb.max - Vec2::new(0.75, 0.25)).length() < 0.001);
+    }
+
+    #[test]
+    fn capsule_ball() {
+        let circle = Collider::Circle(CircleCollider { radius: 0.5 });
+        assert!(contact(Vec2::new(0.8, 0.5), Rot::ZERO, &circle, Vec2::ZERO, Rot::ZERO, &capsule()).is_none());
+
+        // Beside the straight part and above the end cap
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::new(0.7, 0.3), Rot::ZERO, &circle, Vec2::ZERO, Rot::ZERO, &capsule()).unwrap();
+        assert!((normal + Vec2::X).length() < 0.001);
+        assert!((penetration - 0.05).abs() < 0.001);
+
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::new(0., 1.2), Rot::ZERO, &circle, Vec2::ZERO, Rot::ZERO, &capsule()).unwrap();
+        assert!((normal + Vec2::Y).length() < 0.001);
+        assert!((penetration - 0.05).abs() < 0.001);
+    }
+
+    #[test]
+    fn capsule_box() {
+        // A capsule lying on its side on top of a box
+        let r#box = Collider::Box(BoxCollider { size: Vec2::ONE });
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::new(0., 0.7), Rot::from_degrees(90.), &capsule(), Vec2::ZERO, Rot::ZERO, &r#box).unwrap();
+        assert!((normal + Vec2::Y).length() < 0.001);
+        assert!((penetration - 0.05).abs() < 0.001);
+    }
+
+    #[test]
+    fn capsule_capsule() {
+        assert!(contact(Vec2::ZERO, Rot::ZERO, &capsule(), Vec2::new(0.6, 0.), Rot::ZERO, &capsule()).is_none());
+
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::ZERO, Rot::ZERO, &capsule(), Vec2::new(0.4, 0.), Rot::ZERO, &capsule()).unwrap();
+        assert!((normal - Vec2::X).length() < 0.001);
+        assert!((penetration - 0.1).abs() < 0.001);
+    }
+
+    fn polyline(points: &[Vec2]) -> Collider {
+        Collider::Polyline(PolylineCollider {
+            points: points.to_vec(),
+        })
+    }
+
+    #[test]
+    fn polyline_flat_joint_has_no_ghost_contact() {
+        // A diamond poking into the seam between two collinear segments. Each segment on its
+        // own pushes it out diagonally through the shared end point.
+        let flat = polyline(&[Vec2::new(-1., 0.), Vec2::ZERO, Vec2::new(1., 0.)]);
+        let diamond = Collider::Box(BoxCollider { size: Vec2::ONE });
+        let pos = Vec2::new(0.02, 0.5f32.sqrt() - 0.1);
+        let Contact { normal, penetration, r_a, .. } =
+            contact(pos, Rot::from_degrees(45.), &diamond, Vec2::ZERO, Rot::ZERO, &flat).unwrap();
+
+        assert!((normal + Vec2::Y).length() < 0.001);
+        assert!((penetration - 0.1).abs() < 0.001);
+        assert!((pos + r_a - Vec2::new(0.02, -0.1)).length() < 0.001);
+    }
+
+    #[test]
+    fn polyline_box_across_joint_touches_both_segments() {
+        let flat = polyline(&[Vec2::new(-1., 0.), Vec2::ZERO, Vec2::new(1., 0.)]);
+        let r#box = Collider::Box(BoxCollider { size: Vec2::ONE });
+        let mut normals = Vec::new();
+        for_each_contact(Vec2::new(0., 0.45), Rot::ZERO, &r#box, Vec2::ZERO, Rot::ZERO, &flat,
+                         &mut |contact| normals.push(contact.normal));
+        assert_eq!(normals.len(), 2);
+        assert!(normals.iter().all(|normal| (*normal + Vec2::Y).length() < 0.001));
+    }
+
+    #[test]
+    fn polyline_repeated_points() {
+        let flat = polyline(&[Vec2::new(-1., 0.), Vec2::ZERO, Vec2::ZERO, Vec2::new(1., 0.), Vec2::new(1., 0.)]);
+        let circle = Collider::Circle(CircleCollider { radius: 0.5 });
+        let r#box = Collider::Box(BoxCollider { size: Vec2::ONE });
+        for collider in [&circle, &r#box] {
+            for x in [0., 1.] {
+                let mut normals = Vec::new();
+                for_each_contact(Vec2::new(x, 0.4), Rot::ZERO, collider, Vec2::ZERO, Rot::ZERO, &flat,
+                                 &mut |contact| normals.push(contact.normal));
+                assert!(!normals.is_empty());
+                assert!(normals.iter().all(|normal| normal.is_finite()), "{:?}", normals);
+            }
+        }
+    }
+
+    #[test]
+    fn face_contact_clipped_to_segment() {
+        let ball = Ball::new(0.5);
+        let segment = (Vec2::new(-1., 0.), Vec2::new(1., 0.));
+        let face = |x| face_contact(Vec2::new(x, 0.4), Rot::ZERO, &ball, Vec2::ZERO, Rot::ZERO, segment, Vec2::Y);
+        assert!((face(0.5).unwrap().penetration - 0.1).abs() < 0.001);
+        // Past the end the segment's line still cuts the circle, but the segment does not
+        assert!(face(1.2).is_none());
+    }
+
+    #[test]
+    fn polyline_convex_joint_keeps_corner() {
+        // A hill top: a circle resting on the corner is pushed out radially
+        let hill = polyline(&[Vec2::new(-1., -1.), Vec2::ZERO, Vec2::new(1., -1.)]);
+        let circle = Collider::Circle(CircleCollider { radius: 0.5 });
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::new(0.1, 0.3), Rot::ZERO, &circle, Vec2::ZERO, Rot::ZERO, &hill).unwrap();
+
+        assert!((normal + Vec2::new(0.1, 0.3).normalize()).length() < 0.001);
+        assert!((penetration - (0.5 - Vec2::new(0.1, 0.3).length())).abs() < 0.001);
+    }
+
+    #[test]
+    fn polyline_rotated() {
+        let wall = polyline(&[Vec2::new(-1., 0.), Vec2::new(1., 0.)]);
+        let circle = Collider::Circle(CircleCollider { radius: 0.5 });
+        assert!(contact(Vec2::new(0.6, 0.), Rot::ZERO, &circle, Vec2::ZERO, Rot::from_degrees(90.), &wall).is_none());
+
+        let Contact { normal, penetration, .. } =
+            contact(Vec2::new(0.4, 0.), Rot::ZERO, &circle, Vec2::ZERO, Rot::from_degrees(90.), &wall).unwrap();
+        assert!((normal + Vec2::X).length() < 0.001);
+        assert!((penetration - 0.1).abs() < 0.001);
+    }
+
+    #[test]
+    fn polyline_box_order() {
+        let flat = polyline(&[Vec2::new(-1., 0.), Vec2::new(1., 0.)]);
+        let r#box = Collider::Box(BoxCollider { size: Vec2::ONE });
+        let Contact { normal, .. } =
+            contact(Vec2::ZERO, Rot::ZERO, &flat, Vec2::new(0., 0.4), Rot::ZERO, &r#box).unwrap();
+        assert!((normal - Vec2::Y).length() < 0.001);
+        assert!(contact(Vec2::ZERO, Rot::ZERO, &flat, Vec2::ZERO, Rot::ZERO, &flat).is_none());
+    }
+
+    fn dumbbell() -> Collider {
+        let part = |x: f32| CompoundPart {
+            pos: Vec2::new(x, 0.),
+            rot: Rot::ZERO,
+            collider: Collider::Circle(CircleCollider { radius: 0.5 }),
+            mass: 1.,
+        };
+        Collider::Compound(CompoundCollider { parts: vec![part(-1.), part(1.)] })
+    }
+
+    #[test]
+    fn compound_inertia_parallel_axis() {
+        let dumbbell = dumbbell();
+        // Two discs of inertia 1/8 each, one unit away from the center
+        let inertia = 2. * (0.125 + 1.);
+        assert!((dumbbell.inertia_inv_from_mass_inv(0.5) - 1. / inertia).abs() < 0.0001);
+        if let Collider::Compound(compound) = &dumbbell {
+            assert_eq!(compound.center_of_mass(), Vec2::ZERO);
+        }
+    }
+
+    #[test]
+    fn compound_contact_per_part() {
+        // Resting on a floor, both ends touch it
+        let floor = Collider::Box(BoxCollider { size: Vec2::new(10., 1.) });
+        let mut contacts = Vec::new();
+        for_each_contact(Vec2::new(0., 0.9), Rot::ZERO, &dumbbell(), Vec2::ZERO, Rot::ZERO, &floor,
+                         &mut |contact| contacts.push(contact));
+        assert_eq!(contacts.len(), 2);
+        for (contact, x) in contacts.iter().zip([-1., 1.]) {
+            assert!((contact.normal + Vec2::Y).length() < 0.001);
+            assert!((contact.penetration - 0.1).abs() < 0.001);
+            assert!((contact.r_a - Vec2::new(x, -0.5)).length() < 0.001);
+        }
+    }
+
+    #[test]
+    fn compound_rotated_flipped() {
+        // Standing upright, only the lower end reaches the ball below
+        let ball = Collider::Circle(CircleCollider { radius: 0.5 });
+        let Contact { normal, r_b, .. } = contact(
+            Vec2::new(0., -1.9), Rot::ZERO, &ball, Vec2::ZERO, Rot::from_degrees(90.), &dumbbell(),
+        ).unwrap();
+        assert!((normal - Vec2::Y).length() < 0.001);
+        assert!((r_b - Vec2::new(0., -1.5)).length() < 0.001);
+    }
+}
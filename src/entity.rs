@@ -2,23 +2,55 @@ use bevy::prelude::*;
 
 use crate::*;
 
-#[derive(Bundle, Default)]
+#[derive(Bundle)]
 pub struct ParticleBundle {
     pub pos: Pos,
+    pub rot: Rot,
     pub prev_pos: PrevPos,
+    pub prev_rot: PrevRot,
     pub mass: Mass,
-    pub collider: CircleCollider,
+    pub inertia: Inertia,
+    pub collider: Collider,
     pub vel: Vel,
+    pub ang_vel: AngVel,
     pub pre_solve_vel: PreSolveVel,
+    pub pre_solve_ang_vel: PreSolveAngVel,
     pub restitution: Restitution,
+    pub friction: Friction,
     pub aabb: Aabb,
 }
 
+impl Default for ParticleBundle {
+    fn default() -> Self {
+        let mass = Mass::default();
+        let collider = Collider::Circle(CircleCollider::default());
+        let inertia = Inertia {
+            inv: collider.inertia_inv_from_mass_inv(1. / mass.0),
+        };
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            prev_pos: Default::default(),
+            prev_rot: Default::default(),
+            mass,
+            inertia,
+            collider,
+            vel: Default::default(),
+            ang_vel: Default::default(),
+            pre_solve_vel: Default::default(),
+            pre_solve_ang_vel: Default::default(),
+            restitution: Default::default(),
+            friction: Default::default(),
+            aabb: Default::default(),
+        }
+    }
+}
+
 impl ParticleBundle {
     pub fn new_with_pos_and_vel(pos: Vec2, vel: Vec2) -> Self {
         Self {
             pos: Pos(pos),
-            prev_pos: PrevPos(pos - vel * SUB_DT),
+            prev_pos: PrevPos(pos),
             vel: Vel(vel),
             ..Default::default()
         }
@@ -26,7 +58,7 @@ impl ParticleBundle {
 }
 
 //--------------------------------------------------------------------------------------------------
-#[derive(Bundle, Default)]
+#[derive(Bundle)]
 pub struct DynamicBoxBundle {
     pub pos: Pos,
     pub rot: Rot,
@@ -34,20 +66,47 @@ pub struct DynamicBoxBundle {
     pub prev_rot: PrevRot,
     pub mass: Mass,
     pub inertia: Inertia,
-    pub collider: BoxCollider,
+    pub collider: Collider,
     pub vel: Vel,
     pub ang_vel: AngVel,
     pub pre_solve_ang_vel: PreSolveAngVel,
     pub pre_solve_vel: PreSolveVel,
     pub restitution: Restitution,
+    pub friction: Friction,
     pub aabb: Aabb,
 }
 
+impl Default for DynamicBoxBundle {
+    fn default() -> Self {
+        let mass = Mass::default();
+        let collider = Collider::Box(BoxCollider::default());
+        let inertia = Inertia {
+            inv: collider.inertia_inv_from_mass_inv(1. / mass.0),
+        };
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            prev_pos: Default::default(),
+            prev_rot: Default::default(),
+            mass,
+            inertia,
+            collider,
+            vel: Default::default(),
+            ang_vel: Default::default(),
+            pre_solve_ang_vel: Default::default(),
+            pre_solve_vel: Default::default(),
+            restitution: Default::default(),
+            friction: Default::default(),
+            aabb: Default::default(),
+        }
+    }
+}
+
 impl DynamicBoxBundle {
     pub fn new_with_pos_and_vel(pos: Vec2, vel: Vec2) -> Self {
         Self {
             pos: Pos(pos),
-            prev_pos: PrevPos(pos - vel * SUB_DT),
+            prev_pos: PrevPos(pos),
             vel: Vel(vel),
             ..Default::default()
         }
@@ -61,7 +120,7 @@ impl DynamicBoxBundle {
     ) -> Self {
         Self {
             rot,
-            prev_rot: PrevRot(rot.mul(Rot::from_radians(-ang_vel * SUB_DT))),
+            prev_rot: PrevRot(rot),
             ang_vel: AngVel(ang_vel),
             ..Self::new_with_pos_and_vel(pos, vel)
         }
@@ -69,18 +128,317 @@ impl DynamicBoxBundle {
 }
 
 //--------------------------------------------------------------------------------------------------
-#[derive(Bundle, Default)]
+#[derive(Bundle)]
 pub struct StaticCircleBundle {
     pub pos: Pos,
     pub rot: Rot,
-    pub collider: CircleCollider,
+    pub collider: Collider,
     pub restitution: Restitution,
+    pub friction: Friction,
 }
 
-#[derive(Bundle, Default)]
+impl Default for StaticCircleBundle {
+    fn default() -> Self {
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            collider: Collider::Circle(CircleCollider::default()),
+            restitution: Default::default(),
+            friction: Default::default(),
+        }
+    }
+}
+
+#[derive(Bundle)]
 pub struct StaticBoxBundle {
     pub pos: Pos,
     pub rot: Rot,
-    pub collider: BoxCollider,
+    pub collider: Collider,
     pub restitution: Restitution,
-}
\ No newline at end of file
+    pub friction: Friction,
+}
+
+impl Default for StaticBoxBundle {
+    fn default() -> Self {
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            collider: Collider::Box(BoxCollider::default()),
+            restitution: Default::default(),
+            friction: Default::default(),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+#[derive(Bundle)]
+pub struct DynamicCapsuleBundle {
+    pub pos: Pos,
+    pub rot: Rot,
+    pub prev_pos: PrevPos,
+    pub prev_rot: PrevRot,
+    pub mass: Mass,
+    pub inertia: Inertia,
+    pub collider: Collider,
+    pub vel: Vel,
+    pub ang_vel: AngVel,
+    pub pre_solve_ang_vel: PreSolveAngVel,
+    pub pre_solve_vel: PreSolveVel,
+    pub restitution: Restitution,
+    pub friction: Friction,
+    pub aabb: Aabb,
+}
+
+impl Default for DynamicCapsuleBundle {
+    fn default() -> Self {
+        let mass = Mass::default();
+        let collider = Collider::Capsule(CapsuleCollider::default());
+        let inertia = Inertia {
+            inv: collider.inertia_inv_from_mass_inv(1. / mass.0),
+        };
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            prev_pos: Default::default(),
+            prev_rot: Default::default(),
+            mass,
+            inertia,
+            collider,
+            vel: Default::default(),
+            ang_vel: Default::default(),
+            pre_solve_ang_vel: Default::default(),
+            pre_solve_vel: Default::default(),
+            restitution: Default::default(),
+            friction: Default::default(),
+            aabb: Default::default(),
+        }
+    }
+}
+
+impl DynamicCapsuleBundle {
+    pub fn new_with_pos_and_vel(pos: Vec2, vel: Vec2) -> Self {
+        Self {
+            pos: Pos(pos),
+            prev_pos: PrevPos(pos),
+            vel: Vel(vel),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_with_pos_and_vel_and_rot_and_ang_vel(
+        pos: Vec2,
+        vel: Vec2,
+        rot: Rot,
+        ang_vel: f32,
+    ) -> Self {
+        Self {
+            rot,
+            prev_rot: PrevRot(rot),
+            ang_vel: AngVel(ang_vel),
+            ..Self::new_with_pos_and_vel(pos, vel)
+        }
+    }
+}
+
+#[derive(Bundle)]
+pub struct StaticCapsuleBundle {
+    pub pos: Pos,
+    pub rot: Rot,
+    pub collider: Collider,
+    pub restitution: Restitution,
+    pub friction: Friction,
+}
+
+impl Default for StaticCapsuleBundle {
+    fn default() -> Self {
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            collider: Collider::Capsule(CapsuleCollider::default()),
+            restitution: Default::default(),
+            friction: Default::default(),
+        }
+    }
+}
+
+#[derive(Bundle)]
+pub struct StaticPolylineBundle {
+    pub pos: Pos,
+    pub rot: Rot,
+    pub collider: Collider,
+    pub restitution: Restitution,
+    pub friction: Friction,
+}
+
+impl Default for StaticPolylineBundle {
+    fn default() -> Self {
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            collider: Collider::Polyline(PolylineCollider::default()),
+            restitution: Default::default(),
+            friction: Default::default(),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+/// A dynamic body made of the `ChildColliderBundle`s spawned as its children.
+/// Its `Mass` and `Inertia` are computed from the parts.
+#[derive(Bundle)]
+pub struct DynamicCompoundBundle {
+    pub pos: Pos,
+    pub rot: Rot,
+    pub prev_pos: PrevPos,
+    pub prev_rot: PrevRot,
+    pub mass: Mass,
+    pub inertia: Inertia,
+    pub collider: Collider,
+    pub vel: Vel,
+    pub ang_vel: AngVel,
+    pub pre_solve_ang_vel: PreSolveAngVel,
+    pub pre_solve_vel: PreSolveVel,
+    pub restitution: Restitution,
+    pub friction: Friction,
+    pub aabb: Aabb,
+}
+
+impl Default for DynamicCompoundBundle {
+    fn default() -> Self {
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            prev_pos: Default::default(),
+            prev_rot: Default::default(),
+            mass: Default::default(),
+            inertia: Default::default(),
+            collider: Collider::Compound(CompoundCollider::default()),
+            vel: Default::default(),
+            ang_vel: Default::default(),
+            pre_solve_ang_vel: Default::default(),
+            pre_solve_vel: Default::default(),
+            restitution: Default::default(),
+            friction: Default::default(),
+            aabb: Default::default(),
+        }
+    }
+}
+
+impl DynamicCompoundBundle {
+    pub fn new_with_pos_and_vel(pos: Vec2, vel: Vec2) -> Self {
+        Self {
+            pos: Pos(pos),
+            prev_pos: PrevPos(pos),
+            vel: Vel(vel),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Bundle)]
+pub struct StaticCompoundBundle {
+    pub pos: Pos,
+    pub rot: Rot,
+    pub collider: Collider,
+    pub restitution: Restitution,
+    pub friction: Friction,
+}
+
+impl Default for StaticCompoundBundle {
+    fn default() -> Self {
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            collider: Collider::Compound(CompoundCollider::default()),
+            restitution: Default::default(),
+            friction: Default::default(),
+        }
+    }
+}
+
+/// One shape of a compound body, spawned as a child of it. `Pos` and `Rot` are relative to
+/// the parent.
+#[derive(Bundle)]
+pub struct ChildColliderBundle {
+    pub pos: Pos,
+    pub rot: Rot,
+    pub collider: Collider,
+    pub mass: Mass,
+    pub child_collider: ChildCollider,
+}
+
+impl Default for ChildColliderBundle {
+    fn default() -> Self {
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            collider: Collider::Box(BoxCollider::default()),
+            mass: Default::default(),
+            child_collider: ChildCollider,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+/// A moving platform, door or elevator. Set its velocities, or insert a `KinematicTarget`.
+#[derive(Bundle)]
+pub struct KinematicBundle {
+    pub pos: Pos,
+    pub rot: Rot,
+    pub prev_pos: PrevPos,
+    pub prev_rot: PrevRot,
+    pub collider: Collider,
+    pub vel: Vel,
+    pub ang_vel: AngVel,
+    pub pre_solve_ang_vel: PreSolveAngVel,
+    pub pre_solve_vel: PreSolveVel,
+    pub restitution: Restitution,
+    pub friction: Friction,
+    pub aabb: Aabb,
+    pub kinematic: Kinematic,
+}
+
+impl Default for KinematicBundle {
+    fn default() -> Self {
+        Self {
+            pos: Default::default(),
+            rot: Default::default(),
+            prev_pos: Default::default(),
+            prev_rot: Default::default(),
+            collider: Collider::Box(BoxCollider::default()),
+            vel: Default::default(),
+            ang_vel: Default::default(),
+            pre_solve_ang_vel: Default::default(),
+            pre_solve_vel: Default::default(),
+            restitution: Default::default(),
+            friction: Default::default(),
+            aabb: Default::default(),
+            kinematic: Kinematic,
+        }
+    }
+}
+
+impl KinematicBundle {
+    pub fn new_with_pos_and_vel(pos: Vec2, vel: Vec2) -> Self {
+        Self {
+            pos: Pos(pos),
+            prev_pos: PrevPos(pos),
+            vel: Vel(vel),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_with_pos_and_vel_and_rot_and_ang_vel(
+        pos: Vec2,
+        vel: Vec2,
+        rot: Rot,
+        ang_vel: f32,
+    ) -> Self {
+        Self {
+            rot,
+            prev_rot: PrevRot(rot),
+            ang_vel: AngVel(ang_vel),
+            ..Self::new_with_pos_and_vel(pos, vel)
+        }
+    }
+}
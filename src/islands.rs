@@ -0,0 +1,257 @@
+use std::ops::Range;
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::BodyContact;
+
+/// Disjoint sets over `0..len`, with path halving and union by size
+#[derive(Debug, Clone)]
+pub(crate) struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    pub fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
+
+    pub fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    pub fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+
+    /// The sets as lists of members, ordered by their smallest member
+    pub fn groups(&mut self) -> Vec<Vec<usize>> {
+        let mut group_of_root = vec![usize::MAX; self.parent.len()];
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for i in 0..self.parent.len() {
+            let root = self.find(i);
+            if group_of_root[root] == usize::MAX {
+                group_of_root[root] = groups.len();
+                groups.push(Vec::new());
+            }
+            groups[group_of_root[root]].push(i);
+        }
+        groups
+    }
+}
+
+/// Anything the solver handles as a constraint between two bodies
+pub(crate) trait Constraint {
+    fn bodies(&self) -> (Entity, Entity);
+}
+
+impl Constraint for BodyContact {
+    fn bodies(&self) -> (Entity, Entity) {
+        (self.entity_a, self.entity_b)
+    }
+}
+
+/// Bodies connected by constraints, which can be solved independently of all other islands.
+/// Static, kinematic and sleeping bodies do not connect islands and may appear in several.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Island {
+    pub bodies: Vec<Entity>,
+    /// The constraints of the island, a range of the list the islands were built from
+    pub constraints: Range<usize>,
+    /// Indices into `bodies` of the two bodies of each constraint
+    pub constraint_bodies: Vec<(usize, usize)>,
+}
+
+/// Islands over a list of constraints. `ContactIslands` holds the ones of the contacts of the
+/// current substep, rebuilt after the narrow phase.
+#[derive(Debug, Default)]
+pub(crate) struct ConstraintIslands {
+    pub islands: Vec<Island>,
+    /// Indices of the constraints of each colour, only filled for
+    /// `ConstraintOrder::GraphColoring`
+    pub colors: Vec<Vec<usize>>,
+}
+
+impl ConstraintIslands {
+    /// Splits the constraints into islands and reorders them so that each island is contiguous.
+    /// Islands are ordered by their first constraint and keep the order of their constraints,
+    /// so the result only depends on the order of `constraints`. Constraints without any
+    /// dynamic body are moved to the end and belong to no island.
+    pub fn build<T: Constraint>(
+        &mut self,
+        constraints: &mut Vec<T>,
+        is_dynamic: impl Fn(Entity) -> bool,
+        color: bool,
+    ) {
+        self.islands.clear();
+        self.colors.clear();
+
+        let mut index: HashMap<Entity, usize> = HashMap::default();
+        for constraint in constraints.iter() {
+            let (entity_a, entity_b) = constraint.bodies();
+            for entity in [entity_a, entity_b] {
+                if !index.contains_key(&entity) && is_dynamic(entity) {
+                    index.insert(entity, index.len());
+                }
+            }
+        }
+        let mut union_find = UnionFind::new(index.len());
+        for constraint in constraints.iter() {
+            let (entity_a, entity_b) = constraint.bodies();
+            if let (Some(&a), Some(&b)) = (index.get(&entity_a), index.get(&entity_b)) {
+                union_find.union(a, b);
+            }
+        }
+
+        let mut island_of_root: HashMap<usize, usize> = HashMap::default();
+        let mut buckets: Vec<Vec<T>> = Vec::new();
+        let mut unconnected = Vec::new();
+        for constraint in constraints.drain(..) {
+            let (entity_a, entity_b) = constraint.bodies();
+            let body = match index.get(&entity_a).or_else(|| index.get(&entity_b)) {
+                Some(&body) => body,
+                None => {
+                    unconnected.push(constraint);
+                    continue;
+                }
+            };
+            let root = union_find.find(body);
+            let island = *island_of_root.entry(root).or_insert_with(|| {
+                buckets.push(Vec::new());
+                buckets.len() - 1
+            });
+            buckets[island].push(constraint);
+        }
+
+        for bucket in buckets {
+            let start = constraints.len();
+            let mut island = Island::default();
+            let mut local: HashMap<Entity, usize> = HashMap::default();
+            for constraint in bucket.iter() {
+                let mut local_index = |entity: Entity| {
+                    *local.entry(entity).or_insert_with(|| {
+                        island.bodies.push(entity);
+                        island.bodies.len() - 1
+                    })
+                };
+                let (entity_a, entity_b) = constraint.bodies();
+                let pair = (local_index(entity_a), local_index(entity_b));
+                island.constraint_bodies.push(pair);
+            }
+            constraints.extend(bucket);
+            island.constraints = start..constraints.len();
+            self.islands.push(island);
+        }
+
+        if color {
+            let bodies = constraints.iter().map(|constraint| {
+                let (entity_a, entity_b) = constraint.bodies();
+                [index.get(&entity_a).copied(), index.get(&entity_b).copied()]
+            });
+            self.colors = color_constraints(bodies, index.len());
+            // Unconnected constraints do not get solved
+            let solved = constraints.len();
+            for color in self.colors.iter_mut() {
+                color.retain(|&i| i < solved);
+            }
+            self.colors.retain(|color| !color.is_empty());
+        }
+        constraints.extend(unconnected);
+    }
+}
+
+/// The constraint islands of the contacts of the current substep
+#[derive(Debug, Default)]
+pub(crate) struct ContactIslands(pub ConstraintIslands);
+
+/// Greedily colours constraints so that no two constraints of a colour share a dynamic body,
+/// given the indices of their dynamic bodies. Returns the constraints of each colour, in order.
+pub(crate) fn color_constraints(
+    constraints: impl Iterator<Item = [Option<usize>; 2]>,
+    num_bodies: usize,
+) -> Vec<Vec<usize>> {
+    let mut colors: Vec<Vec<usize>> = Vec::new();
+    let mut colors_of_body: Vec<Vec<usize>> = vec![Vec::new(); num_bodies];
+    for (i, bodies) in constraints.enumerate() {
+        let bodies = bodies.iter().flatten();
+        let color = (0..)
+            .find(|color| bodies.clone().all(|&body| !colors_of_body[body].contains(color)))
+            .unwrap();
+        for &body in bodies {
+            colors_of_body[body].push(color);
+        }
+        if color == colors.len() {
+            colors.push(Vec::new());
+        }
+        colors[color].push(i);
+    }
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn union_find_groups() {
+        let mut union_find = UnionFind::new(6);
+        union_find.union(4, 1);
+        union_find.union(2, 5);
+        union_find.union(1, 5);
+        assert_eq!(union_find.groups(), vec![vec![0], vec![1, 2, 4, 5], vec![3]]);
+    }
+
+    #[test]
+    fn contact_islands() {
+        let [a, b, c, d, ground] = [0, 1, 2, 3, 4].map(Entity::from_raw);
+        let contact = |entity_a, entity_b| BodyContact {
+            entity_a,
+            entity_b,
+            local_r_a: Vec2::ZERO,
+            local_r_b: Vec2::ZERO,
+            r_a: Vec2::ZERO,
+            r_b: Vec2::ZERO,
+            normal: Vec2::Y,
+            normal_lagrange: 0.,
+        };
+        let mut contacts = vec![contact(a, b), contact(ground, c), contact(b, ground), contact(c, d)];
+        let mut islands = ConstraintIslands::default();
+        islands.build(&mut contacts, |entity| entity != ground, true);
+
+        // The shared static ground does not connect the two stacks
+        let order: Vec<_> = contacts.iter().map(|contact| (contact.entity_a, contact.entity_b)).collect();
+        assert_eq!(order, vec![(a, b), (b, ground), (ground, c), (c, d)]);
+        assert_eq!(islands.islands.len(), 2);
+        assert_eq!(islands.islands[0].bodies, vec![a, b, ground]);
+        assert_eq!(islands.islands[0].constraints, 0..2);
+        assert_eq!(islands.islands[0].constraint_bodies, vec![(0, 1), (1, 2)]);
+        assert_eq!(islands.islands[1].bodies, vec![ground, c, d]);
+        assert_eq!(islands.islands[1].constraints, 2..4);
+        assert_eq!(islands.islands[1].constraint_bodies, vec![(0, 1), (1, 2)]);
+        // b connects the first two contacts, c the last two; the ground does not count
+        assert_eq!(islands.colors, vec![vec![0, 2], vec![1, 3]]);
+    }
+
+    #[test]
+    fn constraint_colors() {
+        // A chain 0 - 1 - 2 - 3 pinned to static bodies at both ends
+        let constraints = [[None, Some(0)], [Some(0), Some(1)], [Some(1), Some(2)], [Some(2), Some(3)], [Some(3), None]];
+        let colors = color_constraints(constraints.into_iter(), 4);
+        assert_eq!(colors, vec![vec![0, 2, 4], vec![1, 3]]);
+    }
+}
@@ -0,0 +1,500 @@
+use bevy::prelude::*;
+
+use crate::{islands::Constraint, solver::*, Rot};
+
+/// Keeps an anchor point on each of two bodies at `rest_length` from each other. Spawn it on an
+/// entity of its own. With zero `compliance` it is a rigid rod, with positive compliance a
+/// spring.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct DistanceJoint {
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+    /// Anchor on body a, in the local frame of body a
+    pub local_anchor_a: Vec2,
+    /// Anchor on body b, in the local frame of body b
+    pub local_anchor_b: Vec2,
+    pub rest_length: f32,
+    /// Inverse stiffness: zero locks rigidly, larger values let the lock give like a spring. In
+    /// meters per newton for positional locks and radians per newton meter for angular ones,
+    /// for this and every other joint.
+    pub compliance: f32,
+    /// Whether the two bodies keep colliding with each other
+    pub collide_connected: bool,
+}
+
+impl DistanceJoint {
+    pub fn new(entity_a: Entity, entity_b: Entity, local_anchor_a: Vec2, local_anchor_b: Vec2, rest_length: f32) -> Self {
+        Self {
+            entity_a,
+            entity_b,
+            local_anchor_a,
+            local_anchor_b,
+            rest_length,
+            compliance: 0.,
+            collide_connected: false,
+        }
+    }
+
+    fn solve(&self, a: &mut SolverBody, b: &mut SolverBody, sub_dt: f32) -> f32 {
+        let r_a = a.rot.rotate(self.local_anchor_a);
+        let r_b = b.rot.rotate(self.local_anchor_b);
+        let delta = b.pos + r_b - a.pos - r_a;
+        let length = delta.length();
+        // The direction is undefined while the anchors coincide
+        if length <= f32::EPSILON {
+            return 0.;
+        }
+        let correction = delta / length * (length - self.rest_length);
+        solve_positional(a, b, r_a, r_b, correction, PositionalParams::compliant(self.compliance, sub_dt))
+    }
+}
+
+/// Lower and upper bound of the free coordinate of a joint: the angle of body b relative to
+/// body a in radians for a `RevoluteJoint`, the offset along the axis in meters for a
+/// `PrismaticJoint`. The bounds are rigid, whatever the joint's compliance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointLimits {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl JointLimits {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+
+    /// How far `value` lies outside the limits, zero if within
+    fn violation(&self, value: f32) -> f32 {
+        if value < self.min {
+            value - self.min
+        } else if value > self.max {
+            value - self.max
+        } else {
+            0.
+        }
+    }
+}
+
+/// Drives the free coordinate of a joint at `target_velocity`, in radians per second for a
+/// `RevoluteJoint` and meters per second for a `PrismaticJoint`. `max_force` caps the force it
+/// may apply, a torque in newton meters for a `RevoluteJoint`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointMotor {
+    pub target_velocity: f32,
+    pub max_force: f32,
+}
+
+impl JointMotor {
+    pub fn new(target_velocity: f32, max_force: f32) -> Self {
+        Self { target_velocity, max_force }
+    }
+}
+
+/// Pins an anchor point on each of two bodies together, leaving them free to rotate about it,
+/// like a hinge, a wheel axle or an elbow
+#[derive(Component, Debug, Clone, Copy)]
+pub struct RevoluteJoint {
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+    /// Anchor on body a, in the local frame of body a
+    pub local_anchor_a: Vec2,
+    /// Anchor on body b, in the local frame of body b
+    pub local_anchor_b: Vec2,
+    /// Bounds within `[-π, π]`
+    pub limits: Option<JointLimits>,
+    pub motor: Option<JointMotor>,
+    /// Softens the pin only, see `DistanceJoint::compliance`
+    pub compliance: f32,
+    /// Whether the two bodies keep colliding with each other
+    pub collide_connected: bool,
+}
+
+impl RevoluteJoint {
+    pub fn new(entity_a: Entity, entity_b: Entity, local_anchor_a: Vec2, local_anchor_b: Vec2) -> Self {
+        Self {
+            entity_a,
+            entity_b,
+            local_anchor_a,
+            local_anchor_b,
+            limits: None,
+            motor: None,
+            compliance: 0.,
+            collide_connected: false,
+        }
+    }
+
+    /// Rotation of body b relative to body a, in radians
+    fn angle(rot_a: Rot, rot_b: Rot) -> f32 {
+        rot_a.inv().mul(rot_b).as_radians()
+    }
+
+    fn solve(&self, a: &mut SolverBody, b: &mut SolverBody, sub_dt: f32) -> f32 {
+        let r_a = a.rot.rotate(self.local_anchor_a);
+        let r_b = b.rot.rotate(self.local_anchor_b);
+        let delta = b.pos + r_b - a.pos - r_a;
+        let lagrange = solve_positional(a, b, r_a, r_b, delta, PositionalParams::compliant(self.compliance, sub_dt));
+
+        // The motor turns the bodies by the target angle of this substep; the velocities follow
+        // from the rotations like for any other correction
+        if let Some(motor) = self.motor {
+            let start = a.start_rot().inv().mul(b.start_rot());
+            let turned = start.inv().mul(a.rot.inv().mul(b.rot)).as_radians();
+            let params = PositionalParams::rigid(sub_dt).with_max_force(motor.max_force);
+            solve_angular(a, b, turned - motor.target_velocity * sub_dt, params);
+        }
+        if let Some(limits) = self.limits {
+            let violation = limits.violation(Self::angle(a.rot, b.rot));
+            solve_angular(a, b, violation, PositionalParams::rigid(sub_dt));
+        }
+        lagrange
+    }
+}
+
+/// Lets body b slide relative to body a along an axis fixed in body a, with their relative
+/// rotation locked, like a piston, an elevator or a drawer
+#[derive(Component, Debug, Clone, Copy)]
+pub struct PrismaticJoint {
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+    /// Anchor on body a, in the local frame of body a
+    pub local_anchor_a: Vec2,
+    /// Anchor on body b, in the local frame of body b
+    pub local_anchor_b: Vec2,
+    /// Direction of the slide, a unit vector in the local frame of body a
+    pub local_axis_a: Vec2,
+    /// Rotation of body b relative to body a that the joint holds, in radians
+    pub reference_angle: f32,
+    /// Bounds of the offset of the anchor of body b from the one of body a
+    pub limits: Option<JointLimits>,
+    pub motor: Option<JointMotor>,
+    /// Softens both the lock across the axis and the rotation lock, see
+    /// `DistanceJoint::compliance`
+    pub compliance: f32,
+    /// Whether the two bodies keep colliding with each other
+    pub collide_connected: bool,
+}
+
+impl PrismaticJoint {
+    pub fn new(entity_a: Entity, entity_b: Entity, local_anchor_a: Vec2, local_anchor_b: Vec2, local_axis_a: Vec2) -> Self {
+        Self {
+            entity_a,
+            entity_b,
+            local_anchor_a,
+            local_anchor_b,
+            local_axis_a: local_axis_a.normalize(),
+            reference_angle: 0.,
+            limits: None,
+            motor: None,
+            compliance: 0.,
+            collide_connected: false,
+        }
+    }
+
+    fn solve(&self, a: &mut SolverBody, b: &mut SolverBody, sub_dt: f32) -> (f32, f32) {
+        let angle = a.rot.inv().mul(b.rot).mul(Rot::from_radians(-self.reference_angle)).as_radians();
+        let angular_lagrange = solve_angular(a, b, angle, PositionalParams::compliant(self.compliance, sub_dt));
+
+        // Only the offset across the axis is corrected
+        let r_a = a.rot.rotate(self.local_anchor_a);
+        let r_b = b.rot.rotate(self.local_anchor_b);
+        let axis = a.rot.rotate(self.local_axis_a);
+        let delta = b.pos + r_b - a.pos - r_a;
+        let across = delta - axis * delta.dot(axis);
+        let lagrange = solve_positional(a, b, r_a, r_b, across, PositionalParams::compliant(self.compliance, sub_dt));
+
+        if let Some(motor) = self.motor {
+            let r_a = a.rot.rotate(self.local_anchor_a);
+            let r_b = b.rot.rotate(self.local_anchor_b);
+            let start = b.start_pos() + b.start_rot().rotate(self.local_anchor_b)
+                - a.start_pos()
+                - a.start_rot().rotate(self.local_anchor_a);
+            let moved = (b.pos + r_b - a.pos - r_a - start).dot(axis);
+            let correction = axis * (moved - motor.target_velocity * sub_dt);
+            let params = PositionalParams::rigid(sub_dt).with_max_force(motor.max_force);
+            solve_positional(a, b, r_a, r_b, correction, params);
+        }
+        if let Some(limits) = self.limits {
+            let r_a = a.rot.rotate(self.local_anchor_a);
+            let r_b = b.rot.rotate(self.local_anchor_b);
+            let offset = (b.pos + r_b - a.pos - r_a).dot(axis);
+            let correction = axis * limits.violation(offset);
+            solve_positional(a, b, r_a, r_b, correction, PositionalParams::rigid(sub_dt));
+        }
+        (lagrange, angular_lagrange)
+    }
+}
+
+/// Welds two bodies together, locking both their relative position and rotation. With
+/// `break_force` or `break_torque` set, it is removed once holding the bodies together takes
+/// more than that, and a `JointBroken` event is sent.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct FixedJoint {
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+    /// Anchor on body a, in the local frame of body a
+    pub local_anchor_a: Vec2,
+    /// Anchor on body b, in the local frame of body b
+    pub local_anchor_b: Vec2,
+    /// Rotation of body b relative to body a that the joint holds, in radians
+    pub reference_angle: f32,
+    /// Softens both the position and the rotation lock, see `DistanceJoint::compliance`
+    pub compliance: f32,
+    /// Whether the two bodies keep colliding with each other
+    pub collide_connected: bool,
+    /// Force in newtons above which the joint breaks
+    pub break_force: Option<f32>,
+    /// Torque in newton meters above which the joint breaks
+    pub break_torque: Option<f32>,
+}
+
+impl FixedJoint {
+    pub fn new(entity_a: Entity, entity_b: Entity, local_anchor_a: Vec2, local_anchor_b: Vec2) -> Self {
+        Self {
+            entity_a,
+            entity_b,
+            local_anchor_a,
+            local_anchor_b,
+            reference_angle: 0.,
+            compliance: 0.,
+            collide_connected: false,
+            break_force: None,
+            break_torque: None,
+        }
+    }
+
+    fn solve(&self, a: &mut SolverBody, b: &mut SolverBody, sub_dt: f32) -> (f32, f32) {
+        let angle = a.rot.inv().mul(b.rot).mul(Rot::from_radians(-self.reference_angle)).as_radians();
+        let angular_lagrange = solve_angular(a, b, angle, PositionalParams::compliant(self.compliance, sub_dt));
+
+        let r_a = a.rot.rotate(self.local_anchor_a);
+        let r_b = b.rot.rotate(self.local_anchor_b);
+        let delta = b.pos + r_b - a.pos - r_a;
+        let lagrange = solve_positional(a, b, r_a, r_b, delta, PositionalParams::compliant(self.compliance, sub_dt));
+        (lagrange, angular_lagrange)
+    }
+
+    /// Whether the impulses of a solve exceed what the joint can take. Over a substep of
+    /// length `h`, a multiplier `λ` corresponds to a force (or torque) of `λ / h²`.
+    fn breaks(&self, lagrange: f32, angular_lagrange: f32, sub_dt: f32) -> bool {
+        let force = lagrange / (sub_dt * sub_dt);
+        let torque = angular_lagrange.abs() / (sub_dt * sub_dt);
+        self.break_force.is_some_and(|max| force > max) || self.break_torque.is_some_and(|max| torque > max)
+    }
+}
+
+/// Sent when a `FixedJoint` breaks. The component is removed from the `joint` entity.
+#[derive(Debug, Clone, Copy)]
+pub struct JointBroken {
+    pub joint: Entity,
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+}
+
+/// A joint of any kind, as gathered for the solver at the start of a substep
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Joint {
+    Distance(DistanceJoint),
+    Revolute(RevoluteJoint),
+    Prismatic(PrismaticJoint),
+    Fixed(FixedJoint),
+}
+
+impl Joint {
+    /// Solves the joint, returning the multipliers of its positional and angular lock
+    fn solve(&self, a: &mut SolverBody, b: &mut SolverBody, sub_dt: f32) -> (f32, f32) {
+        match self {
+            Joint::Distance(joint) => (joint.solve(a, b, sub_dt), 0.),
+            Joint::Revolute(joint) => (joint.solve(a, b, sub_dt), 0.),
+            Joint::Prismatic(joint) => joint.solve(a, b, sub_dt),
+            Joint::Fixed(joint) => joint.solve(a, b, sub_dt),
+        }
+    }
+}
+
+/// A joint as gathered for the solver, with the entity it is on and the result of its last solve
+#[derive(Debug, Clone)]
+pub(crate) struct SolverJoint {
+    pub entity: Entity,
+    pub joint: Joint,
+    pub lagrange: f32,
+    pub angular_lagrange: f32,
+}
+
+impl SolverJoint {
+    pub fn new(entity: Entity, joint: Joint) -> Self {
+        Self {
+            entity,
+            joint,
+            lagrange: 0.,
+            angular_lagrange: 0.,
+        }
+    }
+
+    pub fn solve(&mut self, a: &mut SolverBody, b: &mut SolverBody, sub_dt: f32) {
+        let (lagrange, angular_lagrange) = self.joint.solve(a, b, sub_dt);
+        self.lagrange = lagrange;
+        self.angular_lagrange = angular_lagrange;
+    }
+
+    pub fn collide_connected(&self) -> bool {
+        match &self.joint {
+            Joint::Distance(joint) => joint.collide_connected,
+            Joint::Revolute(joint) => joint.collide_connected,
+            Joint::Prismatic(joint) => joint.collide_connected,
+            Joint::Fixed(joint) => joint.collide_connected,
+        }
+    }
+
+    /// The event to send if the joint broke in its last solve
+    pub fn broken(&self, sub_dt: f32) -> Option<JointBroken> {
+        match &self.joint {
+            Joint::Fixed(joint) if joint.breaks(self.lagrange, self.angular_lagrange, sub_dt) => Some(JointBroken {
+                joint: self.entity,
+                entity_a: joint.entity_a,
+                entity_b: joint.entity_b,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Constraint for SolverJoint {
+    fn bodies(&self) -> (Entity, Entity) {
+        match &self.joint {
+            Joint::Distance(joint) => (joint.entity_a, joint.entity_b),
+            Joint::Revolute(joint) => (joint.entity_a, joint.entity_b),
+            Joint::Prismatic(joint) => (joint.entity_a, joint.entity_b),
+            Joint::Fixed(joint) => (joint.entity_a, joint.entity_b),
+        }
+    }
+}
+
+/// The joints of the current substep
+#[derive(Debug, Default)]
+pub(crate) struct Joints(pub Vec<SolverJoint>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(pos: Vec2) -> SolverBody {
+        SolverBody {
+            pos,
+            mass_inv: 1.,
+            inertia_inv: 1.,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn distance_joint() {
+        let joint = DistanceJoint::new(Entity::from_raw(0), Entity::from_raw(1), Vec2::ZERO, Vec2::ZERO, 2.);
+
+        // A rigid rod restores the rest length in one go, moving equal masses equally
+        let (mut a, mut b) = (body(Vec2::ZERO), body(Vec2::new(3., 0.)));
+        joint.solve(&mut a, &mut b, 1. / 60.);
+        assert!((a.pos - Vec2::new(0.5, 0.)).length() < 1e-5);
+        assert!((b.pos - Vec2::new(2.5, 0.)).length() < 1e-5);
+
+        // A spring only pulls part of the way
+        let spring = DistanceJoint { compliance: 1e-3, ..joint };
+        let (mut a, mut b) = (body(Vec2::ZERO), body(Vec2::new(3., 0.)));
+        spring.solve(&mut a, &mut b, 1. / 60.);
+        let length = (b.pos - a.pos).length();
+        assert!(length > 2. && length < 3.);
+
+        // A static body does not move
+        let (mut ground, mut b) = (SolverBody::default(), body(Vec2::new(0., -3.)));
+        joint.solve(&mut ground, &mut b, 1. / 60.);
+        assert_eq!(ground.pos, Vec2::ZERO);
+        assert!((b.pos - Vec2::new(0., -2.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn revolute_joint() {
+        let sub_dt = 1. / 60.;
+        let pin = RevoluteJoint::new(Entity::from_raw(0), Entity::from_raw(1), Vec2::ZERO, Vec2::new(-1., 0.));
+
+        // The anchor of the bar is pulled onto the static pivot
+        let (mut pivot, mut bar) = (SolverBody::default(), body(Vec2::new(1.5, 0.)));
+        pin.solve(&mut pivot, &mut bar, sub_dt);
+        assert!((bar.pos + bar.rot.rotate(Vec2::new(-1., 0.))).length() < 1e-5);
+
+        // Past the upper limit, the bar is turned back onto it
+        let limited = RevoluteJoint { limits: Some(JointLimits::new(-0.5, 0.5)), ..pin };
+        let (mut pivot, mut bar) = (SolverBody::default(), body(Vec2::ZERO));
+        bar.rot = Rot::from_radians(0.8);
+        limited.solve(&mut pivot, &mut bar, sub_dt);
+        assert!((bar.rot.as_radians() - 0.5).abs() < 1e-5);
+
+        // The motor turns the bar by its target velocity in one substep, unless the torque
+        // needed exceeds its maximum
+        let motor = RevoluteJoint { motor: Some(JointMotor::new(6., 1e3)), ..limited };
+        let (mut pivot, mut bar) = (SolverBody::default(), body(Vec2::ZERO));
+        bar.prev_rot = Some(Rot::ZERO);
+        motor.solve(&mut pivot, &mut bar, sub_dt);
+        assert!((bar.rot.as_radians() - 6. * sub_dt).abs() < 1e-5);
+        let weak = RevoluteJoint { motor: Some(JointMotor::new(6., 1.)), ..motor };
+        let (mut pivot, mut bar) = (SolverBody::default(), body(Vec2::ZERO));
+        bar.prev_rot = Some(Rot::ZERO);
+        weak.solve(&mut pivot, &mut bar, sub_dt);
+        assert!((bar.rot.as_radians() - sub_dt * sub_dt).abs() < 1e-5);
+    }
+
+    #[test]
+    fn prismatic_joint() {
+        let sub_dt = 1. / 60.;
+        let slider = PrismaticJoint::new(Entity::from_raw(0), Entity::from_raw(1), Vec2::ZERO, Vec2::ZERO, Vec2::X);
+
+        // The offset across the axis and the relative rotation are removed, the offset along
+        // the axis is kept
+        let (mut rail, mut carriage) = (SolverBody::default(), body(Vec2::new(2., 0.5)));
+        carriage.rot = Rot::from_radians(0.3);
+        slider.solve(&mut rail, &mut carriage, sub_dt);
+        assert!((carriage.pos - Vec2::new(2., 0.)).length() < 1e-5);
+        assert!(carriage.rot.as_radians().abs() < 1e-5);
+
+        // Beyond the limits, the carriage is pulled back along the axis
+        let limited = PrismaticJoint { limits: Some(JointLimits::new(-1., 1.)), ..slider };
+        let (mut rail, mut carriage) = (SolverBody::default(), body(Vec2::new(2., 0.)));
+        limited.solve(&mut rail, &mut carriage, sub_dt);
+        assert!((carriage.pos - Vec2::new(1., 0.)).length() < 1e-5);
+
+        // The motor moves the carriage by its target velocity in one substep
+        let motor = PrismaticJoint { motor: Some(JointMotor::new(3., 1e3)), ..slider };
+        let (mut rail, mut carriage) = (SolverBody::default(), body(Vec2::ZERO));
+        carriage.prev_pos = Some(Vec2::ZERO);
+        motor.solve(&mut rail, &mut carriage, sub_dt);
+        assert!((carriage.pos - Vec2::new(3. * sub_dt, 0.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn fixed_joint() {
+        let sub_dt = 1. / 60.;
+        let (ground, entity) = (Entity::from_raw(0), Entity::from_raw(1));
+        let weld = FixedJoint::new(ground, entity, Vec2::ZERO, Vec2::new(-1., 0.));
+
+        // Position and rotation are both restored, over a few iterations as the corrections
+        // rotate the beam
+        let (mut wall, mut beam) = (SolverBody::default(), body(Vec2::new(1.2, -0.1)));
+        beam.rot = Rot::from_radians(-0.2);
+        let mut joint = SolverJoint::new(Entity::from_raw(2), Joint::Fixed(weld));
+        joint.solve(&mut wall, &mut beam, sub_dt);
+        assert!(joint.lagrange > 0. && joint.angular_lagrange != 0.);
+        assert!(joint.broken(sub_dt).is_none());
+        for _ in 0..20 {
+            joint.solve(&mut wall, &mut beam, sub_dt);
+        }
+        assert!((beam.pos - Vec2::new(1., 0.)).length() < 1e-4);
+        assert!(beam.rot.as_radians().abs() < 1e-4);
+
+        // The same correction breaks a weak weld
+        let weak = FixedJoint { break_force: Some(100.), ..weld };
+        let (mut wall, mut beam) = (SolverBody::default(), body(Vec2::new(1.2, -0.1)));
+        beam.rot = Rot::from_radians(-0.2);
+        let mut joint = SolverJoint::new(Entity::from_raw(2), Joint::Fixed(weak));
+        joint.solve(&mut wall, &mut beam, sub_dt);
+        let broken = joint.broken(sub_dt).unwrap();
+        assert_eq!((broken.joint, broken.entity_a, broken.entity_b), (Entity::from_raw(2), ground, entity));
+    }
+}
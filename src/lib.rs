@@ -1,25 +1,33 @@
+mod broad_phase;
 mod components;
 mod contact;
 mod entity;
+mod islands;
+mod joints;
 mod resources;
-mod utils;
 mod rotation;
+mod solver;
 
+pub use broad_phase::*;
 pub use components::*;
 pub use entity::*;
+pub use joints::*;
 pub use resources::*;
 pub use rotation::*;
-use utils::*;
-use contact::Contact;
+use islands::*;
+use solver::*;
 
-use bevy::{prelude::*, ecs::schedule::*};
+use bevy::{
+    prelude::*,
+    ecs::{schedule::*, system::SystemParam},
+    tasks::ComputeTaskPool,
+    transform::TransformSystem,
+    utils::{HashMap, HashSet},
+};
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
 struct FixedUpdateStage;
 
-pub const DELTA_TIME: f32 = 1. / 60.;
-pub const NUM_SUBSTEPS: u32 = 10;
-pub const SUB_DT: f32 = DELTA_TIME / NUM_SUBSTEPS as f32;
 pub const COLLISION_PAIR_VEL_MARGIN_FACTOR: f32 = 0.1;
 
 pub fn startup(
@@ -38,31 +46,41 @@ pub fn startup(
         ..Default::default()
     });
 
+    let collider = Collider::Circle(CircleCollider::default());
+
     // Left particle
+    let mass = Mass(3.);
+    let inertia = Inertia {
+        inv: collider.inertia_inv_from_mass_inv(1. / mass.0),
+    };
     commands
         .spawn_bundle(PbrBundle {
             mesh: sphere.clone(),
             material: white.clone(),
             ..Default::default()
         })
-        .insert_bundle(ParticleBundle::new_with_pos_and_vel(
-            Vec2::new(-2., 0.),
-            Vec2::new(2., 0.),
-        ))
-        .insert(Mass(3.));
+        .insert_bundle(ParticleBundle {
+            mass,
+            inertia,
+            ..ParticleBundle::new_with_pos_and_vel(Vec2::new(-2., 0.), Vec2::new(2., 0.))
+        });
 
     // Right particle
+    let mass = Mass(1.);
+    let inertia = Inertia {
+        inv: collider.inertia_inv_from_mass_inv(1. / mass.0),
+    };
     commands
         .spawn_bundle(PbrBundle {
             mesh: sphere.clone(),
             material: white.clone(),
             ..Default::default()
         })
-        .insert_bundle(ParticleBundle::new_with_pos_and_vel(
-            Vec2::new(2., 0.),
-            Vec2::new(-2., 0.),
-        ))
-        .insert(Mass(1.));
+        .insert_bundle(ParticleBundle {
+            mass,
+            inertia,
+            ..ParticleBundle::new_with_pos_and_vel(Vec2::new(2., 0.), Vec2::new(-2., 0.))
+        });
 
     commands.spawn_bundle(OrthographicCameraBundle {
         transform: Transform::from_translation(Vec3::new(0., 0., 100.)),
@@ -77,30 +95,75 @@ pub fn startup(
 //--------------------------------------------------------------------------------------------------
 #[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
 enum Step {
+    UpdateCompounds,
     CollectCollisionPairs,
     Integrate,
+    NarrowPhase,
+    BuildIslands,
+    CollectJoints,
+    SolveJoints,
     SolvePositions,
     UpdateVelocities,
     SolveVelocities,
+    UpdateSleeping,
+}
+
+/// Keeps the `StaticAabbTree` in sync, touching it only when a static body changed
+fn update_static_aabb_tree(
+    statics: Query<(Entity, &Pos, &Rot, &Collider), (Without<Mass>, Without<Kinematic>, Without<ChildCollider>)>,
+    changed: Query<
+        (),
+        (
+            Without<Mass>,
+            Without<Kinematic>,
+            Without<ChildCollider>,
+            With<Collider>,
+            Or<(Changed<Pos>, Changed<Rot>, Changed<Collider>)>,
+        ),
+    >,
+    mut static_aabb_tree: ResMut<StaticAabbTree>,
+) {
+    if changed.iter().next().is_none() {
+        return;
+    }
+    static_aabb_tree.0.update(statics.iter().map(|(entity, pos, rot, collider)| (entity, collider.aabb(pos.0, *rot))));
+}
+
+/// Drops bodies that were despawned, lost their collider or stopped being static from the
+/// `StaticAabbTree`. Runs every frame, as removals are only reported in the frame they happen.
+fn remove_static_aabbs(
+    removed: RemovedComponents<Collider>,
+    no_longer_static: Query<Entity, Or<(Added<Mass>, Added<Kinematic>, Added<ChildCollider>)>>,
+    mut static_aabb_tree: ResMut<StaticAabbTree>,
+) {
+    for entity in removed.iter().chain(no_longer_static.iter()) {
+        static_aabb_tree.0.remove(entity);
+    }
 }
 
+/// Pairs of dynamic bodies, larger entity first, followed by pairs of a dynamic and a static body
 fn collect_collision_pairs(
     query: Query<(Entity, &Aabb)>,
+    broad_phase: Res<BroadPhase>,
+    static_aabb_tree: Res<StaticAabbTree>,
+    mut sweep_and_prune: ResMut<SweepAndPrune>,
+    mut aabb_tree: ResMut<AabbTree>,
     mut collision_pairs: ResMut<CollisionPairs>,
 ) {
     collision_pairs.0.clear();
+    // The tree also serves the queries of game code, so it is kept filled in both modes
+    aabb_tree.update(query.iter().map(|(entity, aabb)| (entity, *aabb)));
+    match *broad_phase {
+        BroadPhase::SweepAndPrune => {
+            sweep_and_prune.update(query.iter().map(|(entity, aabb)| (entity, *aabb)));
+            sweep_and_prune.collect_pairs(&mut collision_pairs.0);
+        }
+        BroadPhase::AabbTree => aabb_tree.collect_pairs(&mut collision_pairs.0),
+    }
 
-    unsafe {
-        for (entity_a, aabb_a) in query.iter_unsafe() {
-            for (entity_b, aabb_b) in query.iter_unsafe() {
-                // Ensure safety
-                if entity_a <= entity_b {
-                    continue;
-                }
-                if aabb_a.intersects(aabb_b) {
-                    collision_pairs.0.push((entity_a, entity_b));
-                }
-            }
+    for (entity, aabb) in query.iter() {
+        for static_entity in static_aabb_tree.0.region(aabb) {
+            collision_pairs.0.push((entity, static_entity));
         }
     }
 }
@@ -135,7 +198,12 @@ pub fn resume(mut loop_state: ResMut<LoopState>) {
     loop_state.resume();
 }
 
-fn run_criteria(time: Res<Time>, mut state: ResMut<LoopState>) -> ShouldRun {
+fn run_criteria(time: Res<Time>, settings: Res<PhysicsSettings>, mut state: ResMut<LoopState>) -> ShouldRun {
+    // Steps of no length would never use up the accumulator
+    if settings.delta_time <= 0. {
+        return ShouldRun::No;
+    }
+
     if !state.has_added_time {
         state.has_added_time = true;
         state.accumulator += time.delta_seconds();
@@ -144,17 +212,17 @@ fn run_criteria(time: Res<Time>, mut state: ResMut<LoopState>) -> ShouldRun {
     if state.substepping {
         state.current_substep += 1;
 
-        if state.current_substep < NUM_SUBSTEPS {
+        if state.current_substep < settings.substeps() {
             return ShouldRun::YesAndCheckAgain;
         } else {
             // We finished a whole step
-            state.accumulator -= DELTA_TIME;
+            state.accumulator -= settings.delta_time;
             state.current_substep = 0;
             state.substepping = false;
         }
     }
 
-    if state.accumulator >= DELTA_TIME {
+    if state.accumulator >= settings.delta_time {
         state.substepping = true;
         state.current_substep = 0;
         ShouldRun::YesAndCheckAgain
@@ -172,339 +240,667 @@ fn first_substep(state: Res<LoopState>) -> ShouldRun {
     }
 }
 
-fn last_substep(state: Res<LoopState>) -> ShouldRun {
-    if state.current_substep == NUM_SUBSTEPS - 1 {
+fn last_substep(settings: Res<PhysicsSettings>, state: Res<LoopState>) -> ShouldRun {
+    if state.current_substep == settings.substeps() - 1 {
         ShouldRun::Yes
     } else {
         ShouldRun::No
     }
 }
 
+/// Gathers the gravity attractors and zones, so `integrate` can move them too
+fn collect_gravity_sources(
+    attractors: Query<(Entity, &Pos, &GravityAttractor)>,
+    zones: Query<(&Pos, Option<&Rot>, &GravityZone)>,
+    mut sources: ResMut<GravitySources>,
+) {
+    sources.attractors.clear();
+    sources.attractors.extend(attractors.iter().map(|(entity, pos, attractor)| (entity, pos.0, attractor.clone())));
+    sources.zones.clear();
+    sources.zones.extend(zones.iter().map(|(pos, rot, zone)| (pos.0, rot.copied().unwrap_or_default(), zone.clone())));
+}
+
+/// Gathers the joints whose bodies both exist, for the solver and for sleeping
+fn collect_joints(
+    distance_joints: Query<(Entity, &DistanceJoint)>,
+    revolute_joints: Query<(Entity, &RevoluteJoint)>,
+    prismatic_joints: Query<(Entity, &PrismaticJoint)>,
+    fixed_joints: Query<(Entity, &FixedJoint)>,
+    bodies: Query<&Pos>,
+    mut joints: ResMut<Joints>,
+) {
+    joints.0.clear();
+    let distance = distance_joints.iter().map(|(entity, joint)| SolverJoint::new(entity, Joint::Distance(*joint)));
+    let revolute = revolute_joints.iter().map(|(entity, joint)| SolverJoint::new(entity, Joint::Revolute(*joint)));
+    let prismatic = prismatic_joints.iter().map(|(entity, joint)| SolverJoint::new(entity, Joint::Prismatic(*joint)));
+    let fixed = fixed_joints.iter().map(|(entity, joint)| SolverJoint::new(entity, Joint::Fixed(*joint)));
+    joints.0.extend(distance.chain(revolute).chain(prismatic).chain(fixed).filter(|joint| {
+        let (entity_a, entity_b) = joint.bodies();
+        entity_a != entity_b && bodies.get(entity_a).is_ok() && bodies.get(entity_b).is_ok()
+    }));
+}
+
 fn integrate(
-    mut query: Query<(&mut Pos, &mut PrevPos, &mut Vel, &mut PreSolveVel, &Mass)>,
+    mut query: Query<(
+        Entity,
+        &mut Pos,
+        &mut PrevPos,
+        &mut Vel,
+        &mut PreSolveVel,
+        &Mass,
+        Option<&GravityScale>,
+        Option<&ExternalForce>,
+        Option<&LinearDamping>,
+    ), Without<Sleeping>>,
     gravity: Res<Gravity>,
+    gravity_sources: Res<GravitySources>,
+    settings: Res<PhysicsSettings>,
 ) {
-    for (mut pos, mut prev_pos, mut vel, mut pre_solve_vel, mass) in query.iter_mut() {
+    let sub_dt = settings.sub_dt();
+    for (
+        entity,
+        mut pos,
+        mut prev_pos,
+        mut vel,
+        mut pre_solve_vel,
+        mass,
+        gravity_scale,
+        external_force,
+        linear_damping,
+    ) in query.iter_mut()
+    {
         prev_pos.0 = pos.0;
 
-        let gravitation_force = mass.0 * gravity.0;
-        let external_forces = gravitation_force;
-        vel.0 += SUB_DT * external_forces / mass.0;
-        pos.0 += SUB_DT * vel.0;
+        let gravity_scale = gravity_scale.map_or(1., |scale| scale.0);
+        let gravity = (gravity.0 + gravity_sources.acceleration(entity, pos.0)) * gravity_scale;
+        let gravitation_force = mass.0 * gravity;
+        let external_forces = gravitation_force + external_force.map_or(Vec2::ZERO, |force| force.force);
+        vel.0 += sub_dt * external_forces / mass.0;
+        vel.0 *= settings.damping_factor(linear_damping.map_or(settings.linear_damping, |damping| damping.0));
+        pos.0 += sub_dt * vel.0;
         pre_solve_vel.0 = vel.0;
     }
 }
 
-fn integrate_rot(mut query: Query<(&mut Rot, &mut PrevRot, &AngVel, &mut PreSolveAngVel)>) {
-    for (mut rot, mut prev_rot, ang_vel, mut pre_solve_ang_vel) in query.iter_mut() {
+fn integrate_rot(
+    mut query: Query<(
+        &mut Rot,
+        &mut PrevRot,
+        &mut AngVel,
+        &mut PreSolveAngVel,
+        Option<&Inertia>,
+        Option<&ExternalTorque>,
+        Option<&AngularDamping>,
+    ), Without<Sleeping>>,
+    settings: Res<PhysicsSettings>,
+) {
+    let sub_dt = settings.sub_dt();
+    for (mut rot, mut prev_rot, mut ang_vel, mut pre_solve_ang_vel, inertia, external_torque, angular_damping) in
+    query.iter_mut()
+    {
         prev_rot.0 = *rot;
-        *rot = rot.mul(Rot::from_radians(SUB_DT * ang_vel.0));
+        // Kinematic bodies have no inertia and keep the velocity they were given
+        if let Some(inertia) = inertia {
+            if let Some(external_torque) = external_torque {
+                ang_vel.0 += sub_dt * external_torque.torque * inertia.inv;
+            }
+            ang_vel.0 *= settings.damping_factor(angular_damping.map_or(settings.angular_damping, |damping| damping.0));
+        }
+        *rot = rot.mul(Rot::from_radians(sub_dt * ang_vel.0));
         pre_solve_ang_vel.0 = ang_vel.0;
     }
 }
 
-fn update_vel(mut query: Query<(&Pos, &PrevPos, &mut Vel)>) {
-    for (pos, prev_pos, mut vel) in query.iter_mut() {
-        vel.0 = (pos.0 - prev_pos.0) / SUB_DT;
+fn apply_external_impulses(
+    mut query: Query<(&mut ExternalImpulse, &mut Vel, Option<&mut AngVel>, &Mass, Option<&Inertia>), Without<Sleeping>>,
+) {
+    for (mut external_impulse, mut vel, ang_vel, mass, inertia) in query.iter_mut() {
+        vel.0 += external_impulse.impulse / mass.0;
+        if let (Some(mut ang_vel), Some(inertia)) = (ang_vel, inertia) {
+            ang_vel.0 += external_impulse.angular_impulse * inertia.inv;
+        }
+        if !external_impulse.persistent {
+            external_impulse.impulse = Vec2::ZERO;
+            external_impulse.angular_impulse = 0.;
+        }
     }
 }
 
-fn update_ang_vel(mut query: Query<(&Rot, &PrevRot, &mut AngVel)>) {
-    for (rot, prev_rot, mut ang_vel) in query.iter_mut() {
-        ang_vel.0 = (prev_rot.0.inv().mul(*rot)).as_radians() / SUB_DT;
+/// Resets the forces and torques that were applied this step. Those of sleeping bodies were not
+/// applied yet: they woke the body up and are applied in the next step.
+fn clear_external_forces(
+    mut forces: Query<&mut ExternalForce, Without<Sleeping>>,
+    mut torques: Query<&mut ExternalTorque, Without<Sleeping>>,
+) {
+    for mut external_force in forces.iter_mut() {
+        if !external_force.persistent {
+            external_force.force = Vec2::ZERO;
+        }
+    }
+    for mut external_torque in torques.iter_mut() {
+        if !external_torque.persistent {
+            external_torque.torque = 0.;
+        }
     }
 }
 
-fn update_aabb_circle(mut query: Query<(&mut Aabb, &Pos, &CircleCollider)>) {
-    for (mut aabb, pos, circle) in query.iter_mut() {
-        let half_extents = Vec2::splat(circle.radius);
-        aabb.min = pos.0 - half_extents;
-        aabb.max = pos.0 + half_extents;
+/// Sets the velocities of kinematic bodies with a `KinematicTarget` so they reach it by the end
+/// of the step
+fn kinematic_targets(
+    mut query: Query<(&Pos, &Rot, &KinematicTarget, &mut Vel, &mut AngVel), With<Kinematic>>,
+    settings: Res<PhysicsSettings>,
+) {
+    for (pos, rot, target, mut vel, mut ang_vel) in query.iter_mut() {
+        vel.0 = (target.pos - pos.0) / settings.delta_time;
+        ang_vel.0 = rot.inv().mul(target.rot).as_radians() / settings.delta_time;
     }
 }
 
-fn update_aabb_box(mut query: Query<(&mut Aabb, &Pos, &Rot, &Vel, &BoxCollider)>) {
-    for (mut aabb, pos, rot, vel, r#box) in query.iter_mut() {
-        let sin = rot.sin().abs();
-        let cos = rot.cos().abs();
-        let box_w = r#box.size.x;
-        let box_h = r#box.size.y;
-        let w = box_h * sin + box_w * cos;
-        let h = box_w * sin + box_h * cos;
-        let margin = COLLISION_PAIR_VEL_MARGIN_FACTOR * vel.0.length();
-        let half_extents = Vec2::new(w / 2., h / 2.) + Vec2::splat(margin);
-        aabb.min = pos.0 - half_extents;
-        aabb.max = pos.0 + half_extents;
+/// Kinematic bodies follow their velocity, without gravity and without being pushed back
+fn integrate_kinematic(
+    mut query: Query<(&mut Pos, &mut PrevPos, &Vel, &mut PreSolveVel), With<Kinematic>>,
+    settings: Res<PhysicsSettings>,
+) {
+    let sub_dt = settings.sub_dt();
+    for (mut pos, mut prev_pos, vel, mut pre_solve_vel) in query.iter_mut() {
+        prev_pos.0 = pos.0;
+        pos.0 += sub_dt * vel.0;
+        pre_solve_vel.0 = vel.0;
     }
 }
 
-/// Solves overlap between two dynamic bodies according to their masses
-fn constrain_body_positions(
-    pos_a: &mut Pos,
-    pos_b: &mut Pos,
-    mass_a: &Mass,
-    mass_b: &Mass,
-    n: Vec2,
-    penetration_depth: f32,
+fn update_vel(
+    mut query: Query<(&Pos, &PrevPos, &mut Vel), (Without<Kinematic>, Without<Sleeping>)>,
+    settings: Res<PhysicsSettings>,
 ) {
-    let w_a = 1. / mass_a.0;
-    let w_b = 1. / mass_b.0;
-    let w_sum = w_a + w_b;
-    let pos_impulse = n * (-penetration_depth / w_sum);
-    pos_a.0 += pos_impulse * w_a;
-    pos_b.0 -= pos_impulse * w_b;
+    let sub_dt = settings.sub_dt();
+    for (pos, prev_pos, mut vel) in query.iter_mut() {
+        vel.0 = (pos.0 - prev_pos.0) / sub_dt;
+    }
 }
 
-/// Solve a overlap between a dynamic object and a static object
-fn constrain_body_position(pos: &mut Pos, normal: Vec2, penetration_depth: f32) {
-    pos.0 -= normal * penetration_depth;
+fn update_ang_vel(
+    mut query: Query<(&Rot, &PrevRot, &mut AngVel), (Without<Kinematic>, Without<Sleeping>)>,
+    settings: Res<PhysicsSettings>,
+) {
+    let sub_dt = settings.sub_dt();
+    for (rot, prev_rot, mut ang_vel) in query.iter_mut() {
+        ang_vel.0 = (prev_rot.0.inv().mul(*rot)).as_radians() / sub_dt;
+    }
 }
 
-fn solve_pos(
-    mut query: Query<(&mut Pos, &CircleCollider, &Mass)>,
-    mut contacts: ResMut<Contacts>,
-    collision_pairs: Res<CollisionPairs>,
+/// Gathers the `ChildCollider` children of compound bodies into the parent's `Collider`.
+/// For dynamic bodies, `Pos` is moved to the combined center of mass (shifting the children
+/// back so the shapes stay in place) and `Mass` and `Inertia` are recomputed from the parts.
+fn update_compound_colliders(
+    mut bodies: Query<
+        (&mut Collider, &mut Pos, &Rot, &Children, ChangeTrackers<Children>, Option<&mut Mass>, Option<&mut Inertia>),
+        Without<ChildCollider>,
+    >,
+    mut parts: Query<
+        (&mut Pos, &Rot, &Collider, Option<&Mass>, ChangeTrackers<Rot>, ChangeTrackers<Collider>),
+        With<ChildCollider>,
+    >,
 ) {
-    debug!("  solve_pos");
-    for (entity_a, entity_b) in collision_pairs.0.iter().cloned() {
-        if let Ok((
-                      (mut pos_a, circle_a, mass_a),
-                      (mut pos_b, circle_b, mass_b))
-        ) = query.get_pair_mut(entity_a, entity_b) {
-            if let Some(Contact {
-                            normal,
-                            penetration,
-                            r_a,
-                            r_b,
-                        }) = contact::ball_ball(pos_a.0, circle_a.radius, pos_b.0, circle_b.radius)
-            {
-                constrain_body_positions(&mut pos_a, &mut pos_b, mass_a, mass_b, normal, penetration);
-                contacts.0.push(BodyContact {
-                    entity_a,
-                    entity_b,
-                    r_a,
-                    r_b,
-                    normal,
-                });
+    for (mut collider, mut pos, rot, children, children_tracker, mass, inertia) in bodies.iter_mut() {
+        if !matches!(*collider, Collider::Compound(_)) {
+            continue;
+        }
+        let changed = children_tracker.is_changed() || children.iter().any(|&child| {
+            parts.get_mut(child).is_ok_and(|(part_pos, _, _, _, rot_tracker, collider_tracker)| {
+                part_pos.is_changed() || rot_tracker.is_changed() || collider_tracker.is_changed()
+            })
+        });
+        if !changed {
+            continue;
+        }
+
+        let mut compound = CompoundCollider {
+            parts: children
+                .iter()
+                .filter_map(|&child| parts.get(child).ok())
+                .map(|(part_pos, part_rot, part_collider, part_mass, ..)| CompoundPart {
+                    pos: part_pos.0,
+                    rot: *part_rot,
+                    collider: part_collider.clone(),
+                    mass: part_mass.map_or(Mass::default().0, |mass| mass.0),
+                })
+                .collect(),
+        };
+
+        if let Some(mut mass) = mass {
+            let center_of_mass = compound.center_of_mass();
+            if center_of_mass.length_squared() > f32::EPSILON {
+                pos.0 += rot.rotate(center_of_mass);
+                for part in compound.parts.iter_mut() {
+                    part.pos -= center_of_mass;
+                }
+                for &child in children.iter() {
+                    if let Ok((mut part_pos, ..)) = parts.get_mut(child) {
+                        part_pos.0 -= center_of_mass;
+                    }
+                }
+            }
+            if compound.mass() > 0. {
+                mass.0 = compound.mass();
+                if let Some(mut inertia) = inertia {
+                    inertia.inv = compound.inertia_inv_from_mass_inv(1. / mass.0);
+                }
             }
         }
+
+        *collider = Collider::Compound(compound);
+    }
+}
+
+fn update_aabb(mut query: Query<(&mut Aabb, &Pos, &Rot, Option<&Vel>, &Collider), Without<Sleeping>>) {
+    for (mut aabb, pos, rot, vel, collider) in query.iter_mut() {
+        let margin = vel.map_or(0., |vel| COLLISION_PAIR_VEL_MARGIN_FACTOR * vel.0.length());
+        *aabb = collider.aabb(pos.0, *rot);
+        aabb.min -= Vec2::splat(margin);
+        aabb.max += Vec2::splat(margin);
     }
 }
 
-fn solve_pos_statics(
-    mut dynamics: Query<(Entity, &mut Pos, &CircleCollider), With<Mass>>,
-    statics: Query<(Entity, &Pos, &CircleCollider), Without<Mass>>,
-    mut contacts: ResMut<StaticContacts>,
+/// Finds the contacts of the current substep for the pairs found by the broad phase. Bodies
+/// held together by a joint do not collide, unless the joint has `collide_connected` set.
+fn narrow_phase(
+    bodies: Query<(&Pos, &Rot, &Collider, Option<&Mass>, Option<&Sleeping>), Without<ChildCollider>>,
+    collision_pairs: Res<CollisionPairs>,
+    joints: Res<Joints>,
+    mut contacts: ResMut<Contacts>,
 ) {
-    for (entity_a, mut pos_a, circle_a) in dynamics.iter_mut() {
-        for (entity_b, pos_b, circle_b) in statics.iter() {
-            if let Some(Contact {
-                            normal,
-                            penetration,
-                            r_a: _,
-                            r_b: _,
-                        }) = contact::ball_ball(pos_a.0, circle_a.radius, pos_b.0, circle_b.radius)
-            {
-                constrain_body_position(&mut pos_a, normal, penetration);
-                contacts.0.push((entity_a, entity_b, normal));
+    contacts.0.clear();
+
+    let connected: HashSet<(Entity, Entity)> = joints
+        .0
+        .iter()
+        .filter(|joint| !joint.collide_connected())
+        .map(|joint| {
+            let (entity_a, entity_b) = joint.bodies();
+            (entity_a.max(entity_b), entity_a.min(entity_b))
+        })
+        .collect();
+
+    for (entity_a, entity_b) in collision_pairs.0.iter().cloned() {
+        if connected.contains(&(entity_a.max(entity_b), entity_a.min(entity_b))) {
+            continue;
+        }
+        if let (
+            Ok((pos_a, rot_a, collider_a, mass_a, sleeping_a)),
+            Ok((pos_b, rot_b, collider_b, mass_b, sleeping_b)),
+        ) = (bodies.get(entity_a), bodies.get(entity_b))
+        {
+            // Neither body would respond, e.g. two kinematic or two sleeping bodies
+            let responds_a = mass_a.is_some() && sleeping_a.is_none();
+            let responds_b = mass_b.is_some() && sleeping_b.is_none();
+            if !responds_a && !responds_b {
+                continue;
             }
+            contact::for_each_contact(pos_a.0, *rot_a, collider_a, pos_b.0, *rot_b, collider_b, &mut |contact| {
+                contacts.0.push(BodyContact::new(entity_a, entity_b, *rot_a, *rot_b, contact))
+            });
         }
     }
 }
 
-fn solve_pos_static_boxes(
-    mut dynamics: Query<(Entity, &mut Pos, &CircleCollider), With<Mass>>,
-    statics: Query<(Entity, &Pos, &BoxCollider), Without<Mass>>,
-    mut contacts: ResMut<StaticContacts>,
+/// Splits the contacts of the substep into islands that can be solved in parallel
+fn build_contact_islands(
+    bodies: Query<(Option<&Mass>, Option<&Sleeping>)>,
+    mut contacts: ResMut<Contacts>,
+    mut islands: ResMut<ContactIslands>,
+    settings: Res<PhysicsSettings>,
 ) {
-    for (entity_a, mut pos_a, circle_a) in dynamics.iter_mut() {
-        for (entity_b, pos_b, box_b) in statics.iter() {
-            if let Some(Contact {
-                            normal,
-                            penetration,
-                            r_a: _,
-                            r_b: _,
-                        }) = contact::ball_box(pos_a.0, circle_a.radius, pos_b.0, box_b.size)
-            {
-                constrain_body_position(&mut pos_a, normal, penetration);
-                contacts.0.push((entity_a, entity_b, normal));
-            }
+    islands.0.build(
+        &mut contacts.0,
+        |entity| bodies.get(entity).is_ok_and(|(mass, sleeping)| mass.is_some() && sleeping.is_none()),
+        settings.constraint_order == ConstraintOrder::GraphColoring,
+    );
+}
+
+/// Solves the joints in their own islands, before the contacts get the last word
+fn solve_joints(
+    mut bodies: Query<(
+        &mut Pos,
+        &mut Rot,
+        Option<&PrevPos>,
+        Option<&PrevRot>,
+        Option<&Mass>,
+        Option<&Inertia>,
+        Option<&Sleeping>,
+    )>,
+    mut joints: ResMut<Joints>,
+    settings: Res<PhysicsSettings>,
+    pool: Res<ComputeTaskPool>,
+) {
+    if joints.0.is_empty() {
+        return;
+    }
+    let order = settings.constraint_order;
+    let mut islands = ConstraintIslands::default();
+    islands.build(
+        &mut joints.0,
+        |entity| bodies.get(entity).is_ok_and(|(.., mass, _, sleeping)| mass.is_some() && sleeping.is_none()),
+        order == ConstraintOrder::GraphColoring,
+    );
+
+    let solver_bodies = gather_bodies(&islands, |entity| {
+        bodies.get(entity).map_or_else(
+            |_| SolverBody::default(),
+            |(pos, rot, prev_pos, prev_rot, mass, inertia, sleeping)| {
+                let (mass_inv, inertia_inv) = inverse_mass(mass, inertia, sleeping);
+                // Sleeping bodies have not moved since they fell asleep
+                let moved = sleeping.is_none();
+                SolverBody {
+                    pos: pos.0,
+                    rot: *rot,
+                    prev_pos: prev_pos.filter(|_| moved).map(|prev_pos| prev_pos.0),
+                    prev_rot: prev_rot.filter(|_| moved).map(|prev_rot| prev_rot.0),
+                    mass_inv,
+                    inertia_inv,
+                    ..Default::default()
+                }
+            },
+        )
+    });
+
+    let sub_dt = settings.sub_dt();
+    let solved = solve_constraints(&pool, &islands, order, solver_bodies, &mut joints.0, |joint, a, b| {
+        joint.solve(a, b, sub_dt)
+    });
+
+    scatter_bodies(&islands, solved, |entity, body| {
+        if let Ok((mut pos, mut rot, ..)) = bodies.get_mut(entity) {
+            pos.0 = body.pos;
+            *rot = body.rot;
         }
+    });
+}
+
+/// Removes the fixed joints that were strained past their break force or torque
+fn break_joints(
+    mut commands: Commands,
+    joints: Res<Joints>,
+    settings: Res<PhysicsSettings>,
+    mut broken_events: EventWriter<JointBroken>,
+) {
+    for broken in joints.0.iter().filter_map(|joint| joint.broken(settings.sub_dt())) {
+        commands.entity(broken.joint).remove::<FixedJoint>();
+        broken_events.send(broken);
     }
 }
 
-fn solve_pos_box_box(
-    mut query: Query<(&mut Pos, &mut Rot, &BoxCollider, &Mass, &Inertia)>,
+fn solve_pos(
+    mut bodies: Query<(
+        &mut Pos,
+        &mut Rot,
+        Option<&PrevPos>,
+        Option<&PrevRot>,
+        Option<&Mass>,
+        Option<&Inertia>,
+        Option<&Sleeping>,
+        Option<&Friction>,
+    )>,
+    islands: Res<ContactIslands>,
     mut contacts: ResMut<Contacts>,
-    collision_pairs: Res<CollisionPairs>,
+    settings: Res<PhysicsSettings>,
+    pool: Res<ComputeTaskPool>,
 ) {
-    for (entity_a, entity_b) in collision_pairs.0.iter().cloned() {
-        if let Ok(((mut pos_a, mut rot_a, box_a, mass_a, inertia_a), (mut pos_b, mut rot_b, box_b, mass_b, inertia_b))) =
-        query.get_pair_mut(entity_a, entity_b)
-        {
-            if let Some(Contact {
-                            normal,
-                            penetration,
-                            r_a,
-                            r_b,
-                        }) = contact::box_box(pos_a.0, *rot_a, box_a.size, pos_b.0, *rot_b, box_b.size)
-            {
-                let mass_a_inv = 1. / mass_a.0;
-                let mass_b_inv = 1. / mass_b.0;
-
-                let inertia_a_inv = 12. * mass_a_inv / box_a.size.length_squared();
-                let inertia_b_inv = 12. * mass_b_inv / box_b.size.length_squared();
-
-                let w_a_rot = inertia_a.inv * r_a.perp_dot(normal).powi(2);
-                let w_b_rot = inertia_b.inv * r_b.perp_dot(normal).powi(2);
-
-                let w_a = mass_a_inv + w_a_rot;
-                let w_b = mass_b_inv + w_b_rot;
-
-                let w = w_a + w_b;
-                let pos_impulse = normal * (-penetration / w);
-
-                pos_a.0 += pos_impulse * w_a;
-                pos_b.0 -= pos_impulse * w_b;
-
-                *rot_a = rot_a.mul(Rot::from_radians(inertia_a.inv * r_a.perp_dot(pos_impulse)));
-                *rot_b = rot_b.mul(Rot::from_radians(
-                    inertia_b.inv * r_b.perp_dot(-pos_impulse),
-                ));
-
-                contacts.0.push(BodyContact {
-                    entity_a,
-                    entity_b,
-                    r_a,
-                    r_b,
-                    normal,
-                });
+    debug!("  solve_pos");
+    let solver_bodies = gather_bodies(&islands.0, |entity| {
+        bodies.get(entity).map_or_else(
+            |_| SolverBody::default(),
+            |(pos, rot, prev_pos, prev_rot, mass, inertia, sleeping, friction)| {
+                let (mass_inv, inertia_inv) = inverse_mass(mass, inertia, sleeping);
+                let default_friction = Friction::default();
+                let friction = friction.unwrap_or(&default_friction);
+                // Sleeping bodies have not moved since they fell asleep
+                let moved = sleeping.is_none();
+                SolverBody {
+                    pos: pos.0,
+                    rot: *rot,
+                    prev_pos: prev_pos.filter(|_| moved).map(|prev_pos| prev_pos.0),
+                    prev_rot: prev_rot.filter(|_| moved).map(|prev_rot| prev_rot.0),
+                    mass_inv,
+                    inertia_inv,
+                    static_friction: friction.static_coefficient,
+                    dynamic_friction: friction.dynamic_coefficient,
+                    ..Default::default()
+                }
+            },
+        )
+    });
+
+    let sub_dt = settings.sub_dt();
+    let order = settings.constraint_order;
+    let solved = solve_constraints(&pool, &islands.0, order, solver_bodies, &mut contacts.0, |contact, a, b| {
+        solve_contact_pos(contact, a, b, sub_dt)
+    });
+
+    scatter_bodies(&islands.0, solved, |entity, body| {
+        if let Ok((mut pos, mut rot, ..)) = bodies.get_mut(entity) {
+            pos.0 = body.pos;
+            *rot = body.rot;
+        }
+    });
+}
+
+fn solve_vel(
+    mut bodies: Query<(
+        Option<&mut Vel>,
+        Option<&mut AngVel>,
+        Option<&PreSolveVel>,
+        Option<&PreSolveAngVel>,
+        Option<&Mass>,
+        Option<&Inertia>,
+        Option<&Sleeping>,
+        Option<&Restitution>,
+        Option<&Friction>,
+    )>,
+    islands: Res<ContactIslands>,
+    mut contacts: ResMut<Contacts>,
+    settings: Res<PhysicsSettings>,
+    pool: Res<ComputeTaskPool>,
+) {
+    let solver_bodies = gather_bodies(&islands.0, |entity| {
+        bodies.get(entity).map_or_else(
+            |_| SolverBody::default(),
+            |(vel, ang_vel, pre_solve_vel, pre_solve_ang_vel, mass, inertia, sleeping, restitution, friction)| {
+                let (mass_inv, inertia_inv) = inverse_mass(mass, inertia, sleeping);
+                let restitution = restitution.map_or(Restitution::default().0, |restitution| restitution.0);
+                let default_friction = Friction::default();
+                let friction = friction.unwrap_or(&default_friction);
+                let body = SolverBody {
+                    mass_inv,
+                    inertia_inv,
+                    static_friction: friction.static_coefficient,
+                    dynamic_friction: friction.dynamic_coefficient,
+                    restitution,
+                    ..Default::default()
+                };
+                // Sleeping bodies stand still
+                if sleeping.is_some() {
+                    return body;
+                }
+                SolverBody {
+                    vel: vel.map_or(Vec2::ZERO, |vel| vel.0),
+                    ang_vel: ang_vel.map_or(0., |ang_vel| ang_vel.0),
+                    pre_solve_vel: pre_solve_vel.map_or(Vec2::ZERO, |vel| vel.0),
+                    pre_solve_ang_vel: pre_solve_ang_vel.map_or(0., |ang_vel| ang_vel.0),
+                    ..body
+                }
+            },
+        )
+    });
+
+    let sub_dt = settings.sub_dt();
+    let order = settings.constraint_order;
+    let solved = solve_constraints(&pool, &islands.0, order, solver_bodies, &mut contacts.0, |contact, a, b| {
+        solve_contact_vel(contact, a, b, sub_dt)
+    });
+
+    scatter_bodies(&islands.0, solved, |entity, body| {
+        if let Ok((vel, ang_vel, ..)) = bodies.get_mut(entity) {
+            if let Some(mut vel) = vel {
+                vel.0 = body.vel;
+            }
+            if let Some(mut ang_vel) = ang_vel {
+                ang_vel.0 = body.ang_vel;
             }
         }
+    });
+}
+
+/// What keeps sleeping islands in touch with the rest of the world
+#[derive(SystemParam)]
+struct SleepLinks<'w, 's> {
+    contacts: Res<'w, Contacts>,
+    joints: Res<'w, Joints>,
+    collision_pairs: Res<'w, CollisionPairs>,
+    kinematics: Query<'w, 's, (&'static Vel, &'static AngVel), With<Kinematic>>,
+}
+
+impl<'w, 's> SleepLinks<'w, 's> {
+    /// Bodies in contact or held together by a joint
+    fn links(&self) -> Vec<(Entity, Entity)> {
+        self.contacts
+            .0
+            .iter()
+            .map(|contact| (contact.entity_a, contact.entity_b))
+            .chain(self.joints.0.iter().map(|joint| joint.bodies()))
+            .collect()
+    }
+
+    fn is_moving_kinematic(&self, entity: Entity) -> bool {
+        self.kinematics.get(entity).is_ok_and(|(vel, ang_vel)| vel.0 != Vec2::ZERO || ang_vel.0 != 0.)
     }
 }
 
-fn solve_pos_static_box_box(
-    mut dynamics: Query<(Entity, &mut Pos, &mut Rot, &BoxCollider, &Mass, &Inertia)>,
-    statics: Query<(Entity, &Pos, &Rot, &BoxCollider), Without<Mass>>,
-    mut contacts: ResMut<StaticContacts>,
+/// Puts contact islands to sleep once all their bodies have been resting for
+/// `PhysicsSettings::time_to_sleep`, and wakes sleeping islands that are touched by a moving
+/// body or pushed by the user
+fn update_sleeping(
+    mut commands: Commands,
+    mut bodies: Query<
+        (
+            Entity,
+            &Vel,
+            &AngVel,
+            Option<&SleepThreshold>,
+            Option<&mut SleepTimer>,
+            Option<&ExternalForce>,
+            Option<&ExternalTorque>,
+            Option<&Sleeping>,
+        ),
+        With<Mass>,
+    >,
+    pushed: Query<
+        (
+            Entity,
+            ChangeTrackers<Vel>,
+            ChangeTrackers<AngVel>,
+            Option<&ExternalForce>,
+            Option<&ExternalTorque>,
+            Option<&ExternalImpulse>,
+        ),
+        (With<Sleeping>, With<Mass>),
+    >,
+    links: SleepLinks,
+    settings: Res<PhysicsSettings>,
+    mut islands: ResMut<SleepingIslands>,
 ) {
-    for (entity_a, mut pos_a, mut rot_a, box_a, mass_a, inertia_a) in dynamics.iter_mut() {
-        for (entity_b, pos_b, rot_b, box_b) in statics.iter() {
-            if let Some(Contact {
-                            normal,
-                            penetration,
-                            r_a,
-                            r_b,
-                        }) = contact::box_box(pos_a.0, *rot_a, box_a.size, pos_b.0, *rot_b, box_b.size)
-            {
-                let mass_inv = 1. / mass_a.0;
-                let i_inv = 12. * mass_inv / box_a.size.length_squared();
-                let w_rot = inertia_a.inv * r_a.perp_dot(normal).powi(2);
-                let w = mass_inv + w_rot;
-                let p = -normal * penetration / w;
-                pos_a.0 += p * mass_inv;
-                let delta_rot = inertia_a.inv * r_a.perp_dot(p);
-                *rot_a = rot_a.mul(Rot::from_radians(delta_rot));
-                contacts.0.push((entity_a, entity_b, normal));
+    // Awake bodies, and whether they have been resting long enough to sleep
+    let mut ready: HashMap<Entity, bool> = HashMap::default();
+    for (entity, vel, ang_vel, threshold, timer, force, torque, sleeping) in bodies.iter_mut() {
+        if sleeping.is_some() {
+            continue;
+        }
+        // A body held still against a persistent force would be woken by it right away
+        let driven = force.is_some_and(|force| force.persistent && force.force != Vec2::ZERO)
+            || torque.is_some_and(|torque| torque.persistent && torque.torque != 0.);
+        let threshold = threshold.unwrap_or(&settings.sleep_threshold);
+        let resting = vel.0.length() < threshold.linear && ang_vel.0.abs() < threshold.angular;
+        let time = match timer {
+            Some(mut timer) => {
+                timer.0 = if resting { timer.0 + settings.delta_time } else { 0. };
+                timer.0
             }
+            None => {
+                commands.entity(entity).insert(SleepTimer(0.));
+                0.
+            }
+        };
+        ready.insert(entity, settings.allow_sleeping && !driven && time >= settings.time_to_sleep);
+    }
+
+    let mut to_wake = if settings.allow_sleeping { Vec::new() } else { islands.ids() };
+    // Moving bodies wake the islands they touch
+    let link_list = links.links();
+    let touching = link_list.iter().chain(links.collision_pairs.0.iter()).copied();
+    to_wake.extend(islands.touched(touching, |entity| {
+        ready.get(&entity) == Some(&false) || links.is_moving_kinematic(entity)
+    }));
+    for (entity, vel, ang_vel, force, torque, impulse) in pushed.iter() {
+        let pushed = vel.is_changed()
+            || ang_vel.is_changed()
+            || force.is_some_and(|force| force.force != Vec2::ZERO)
+            || torque.is_some_and(|torque| torque.torque != 0.)
+            || impulse.is_some_and(|impulse| impulse.impulse != Vec2::ZERO || impulse.angular_impulse != 0.);
+        if pushed {
+            to_wake.extend(islands.island_of(entity));
         }
     }
+    for island in to_wake {
+        for entity in islands.wake(island) {
+            if bodies.get(entity).is_ok() {
+                commands.entity(entity).remove::<Sleeping>().insert(SleepTimer(0.));
+            }
+        }
+    }
+
+    for (members, merged) in islands.resting(&ready, &link_list) {
+        for &entity in members.iter() {
+            commands.entity(entity).insert(Sleeping);
+        }
+        islands.sleep(members, &merged);
+    }
 }
 
-fn solve_vel(
-    mut query: Query<(
-        &mut Vel,
-        &mut AngVel,
-        &PreSolveVel,
-        &PreSolveAngVel,
-        &Mass,
-        &Inertia,
-        &Restitution,
-    )>,
-    contacts: Res<Contacts>,
+/// Remembers the pose at the start of the step for `interpolate_transforms`
+fn store_prev_step_pose(
+    mut commands: Commands,
+    mut query: Query<(Entity, &Pos, &Rot, Option<&mut PrevStepPos>, Option<&mut PrevStepRot>)>,
+    settings: Res<PhysicsSettings>,
 ) {
-    for BodyContact {
-        entity_a,
-        entity_b,
-        r_a,
-        r_b,
-        normal: n,
-    } in contacts.0.iter().cloned() {
-        let (
-            (mut vel_a, mut ang_vel_a, pre_solve_vel_a, pre_solve_ang_vel_a, mass_a, inertia_a, restitution_a),
-            (mut vel_b, mut ang_vel_b, pre_solve_vel_b, pre_solve_ang_vel_b, mass_b, inertia_b, restitution_b),
-        ) = query.get_pair_mut(entity_a, entity_b).unwrap();
-        let pre_solve_relative_vel = pre_solve_vel_a.0 - pre_solve_vel_b.0;
-        let pre_solve_normal_vel = Vec2::dot(pre_solve_relative_vel, n);
-
-        let relative_vel = vel_a.0 - vel_b.0;
-        let normal_vel = Vec2::dot(relative_vel, n);
-        let restitution = (restitution_a.0 + restitution_b.0) / 2.;
-
-        let w_a = 1. / mass_a.0;
-        let w_b = 1. / mass_b.0;
-        let w_sum = w_a + w_b;
-
-        let restitution_velocity = (-restitution * pre_solve_normal_vel).min(0.);
-        let vel_impulse = n * ((-normal_vel + restitution_velocity) / w_sum);
-
-        let i_inv_a = 67.;
-        let i_inv_b = 67.;
-        // angle
-        let contact_vel_a = vel_a.0 + ang_vel_a.0 * r_a.perp();
-        let contact_vel_b = vel_b.0 + ang_vel_b.0 * r_b.perp();
-        let relative_vel = contact_vel_a - contact_vel_b;
-        let pre_solve_contact_vel_a = pre_solve_vel_a.0 + pre_solve_ang_vel_a.0 * r_a.perp();
-        let pre_solve_contact_vel_b = pre_solve_vel_b.0 + pre_solve_ang_vel_b.0 * r_b.perp();
-        let pre_solve_relative_vel = pre_solve_contact_vel_a - pre_solve_contact_vel_b;
-        let w_rot_a = inertia_a.inv * r_a.perp_dot(n).powi(2);
-        let w_rot_b = inertia_b.inv * r_b.perp_dot(n).powi(2);
-
-        let w_a = 1. / mass_a.0 + w_rot_a;
-        let w_b = 1. / mass_b.0 + w_rot_b;
-
-        let w_sum = w_a + w_b;
-
-        let restitution_velocity = (-restitution * pre_solve_normal_vel).min(0.);
-        let vel_impulse = n * ((-normal_vel + restitution_velocity) / w_sum);
-
-        vel_a.0 += vel_impulse / mass_a.0;
-        vel_b.0 -= vel_impulse / mass_b.0;
-
-        ang_vel_a.0 += inertia_a.inv * r_a.perp_dot(vel_impulse);
-        ang_vel_b.0 += inertia_b.inv * r_b.perp_dot(-vel_impulse);
-    }
-}
-
-fn solve_vel_statics(
-    mut dynamics: Query<(
-        &mut Vel,
-        &mut AngVel,
-        &PreSolveVel,
-        &PreSolveAngVel,
-        &Mass,
-        &Inertia,
-        &Restitution,
-    )>,
-    statics: Query<&Restitution, Without<Mass>>,
-    contacts: Res<StaticContacts>,
+    if !settings.interpolate_transforms {
+        return;
+    }
+    for (entity, pos, rot, prev_step_pos, prev_step_rot) in query.iter_mut() {
+        match (prev_step_pos, prev_step_rot) {
+            (Some(mut prev_step_pos), Some(mut prev_step_rot)) => {
+                prev_step_pos.0 = pos.0;
+                prev_step_rot.0 = *rot;
+            }
+            _ => {
+                commands.entity(entity).insert(PrevStepPos(pos.0)).insert(PrevStepRot(*rot));
+            }
+        }
+    }
+}
+
+/// Blends Transforms between the last two steps by the fraction of a step left in the accumulator
+fn interpolate_transforms(
+    mut query: Query<(&mut Transform, &Pos, &Rot, &PrevStepPos, &PrevStepRot)>,
+    settings: Res<PhysicsSettings>,
+    state: Res<LoopState>,
 ) {
-    for (entity_a, entity_b, n) in contacts.0.iter().cloned() {
-        let (
-            mut vel_a,
-            mut ang_vel_a,
-            pre_solve_vel_a,
-            pre_solve_ang_vel_a,
-            mass_a,
-            inertia_a,
-            restitution_a,
-        ) = dynamics.get_mut(entity_a).unwrap();
-        let restitution_b = statics.get(entity_b).unwrap();
-        let pre_solve_normal_vel = Vec2::dot(pre_solve_vel_a.0, n);
-        let normal_vel = Vec2::dot(vel_a.0, n);
-        let restitution = (restitution_a.0 + restitution_b.0) / 2.;
-        vel_a.0 += n * (-normal_vel + (-restitution * pre_solve_normal_vel).min(0.));
+    if !settings.interpolate_transforms || settings.delta_time <= 0. {
+        return;
+    }
+    let alpha = (state.accumulator / settings.delta_time).clamp(0., 1.);
+    for (mut transform, pos, rot, prev_step_pos, prev_step_rot) in query.iter_mut() {
+        transform.translation = prev_step_pos.0.lerp(pos.0, alpha).extend(0.);
+        transform.rotation = prev_step_rot.0.slerp(*rot, alpha).into();
     }
 }
 
@@ -522,20 +918,39 @@ pub struct XPBDPlugin;
 impl Plugin for XPBDPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Gravity>()
+            .init_resource::<PhysicsSettings>()
+            .init_resource::<GravitySources>()
+            .init_resource::<SleepingIslands>()
             .init_resource::<CollisionPairs>()
+            .init_resource::<BroadPhase>()
+            .init_resource::<SweepAndPrune>()
+            .init_resource::<AabbTree>()
+            .init_resource::<StaticAabbTree>()
             .init_resource::<Contacts>()
-            .init_resource::<StaticContacts>()
+            .init_resource::<ContactIslands>()
+            .init_resource::<Joints>()
+            .add_event::<JointBroken>()
             .init_resource::<LoopState>()
             .add_stage_before(
                 CoreStage::Update,
                 FixedUpdateStage,
                 SystemStage::parallel()
                     .with_run_criteria(run_criteria)
-                    .with_system_set(
-                        SystemSet::new()
+                    .with_system(
+                        update_compound_colliders
+                            .with_run_criteria(first_substep)
+                            .label(Step::UpdateCompounds)
+                    )
+                    .with_system(
+                        update_aabb
+                            .after(Step::UpdateCompounds)
+                            .before(Step::CollectCollisionPairs)
+                    )
+                    .with_system(
+                        update_static_aabb_tree
+                            .with_run_criteria(first_substep)
+                            .after(Step::UpdateCompounds)
                             .before(Step::CollectCollisionPairs)
-                            .with_system(update_aabb_box)
-                            .with_system(update_aabb_circle)
                     )
                     .with_system(
                         collect_collision_pairs
@@ -543,21 +958,63 @@ impl Plugin for XPBDPlugin {
                             .label(Step::CollectCollisionPairs)
                             .before(Step::Integrate),
                     )
+                    .with_system(
+                        store_prev_step_pose
+                            .with_run_criteria(first_substep)
+                            .after(Step::UpdateCompounds)
+                            .before(Step::Integrate)
+                    )
+                    .with_system(
+                        kinematic_targets
+                            .with_run_criteria(first_substep)
+                            .before(Step::Integrate)
+                    )
+                    .with_system(
+                        apply_external_impulses
+                            .with_run_criteria(first_substep)
+                            .before(Step::Integrate)
+                    )
+                    .with_system(
+                        collect_gravity_sources
+                            .before(Step::Integrate)
+                    )
                     .with_system_set(
                         SystemSet::new()
                             .label(Step::Integrate)
                             .with_system(integrate)
+                            .with_system(integrate_kinematic)
                             .with_system(integrate_rot),
                     )
-                    .with_system_set(
-                        SystemSet::new()
-                            .label(Step::SolvePositions)
+                    .with_system(
+                        narrow_phase
+                            .label(Step::NarrowPhase)
+                            .after(Step::CollectJoints)
+                    )
+                    .with_system(
+                        build_contact_islands
+                            .label(Step::BuildIslands)
+                            .after(Step::NarrowPhase)
+                    )
+                    .with_system(
+                        collect_joints
+                            .label(Step::CollectJoints)
                             .after(Step::Integrate)
-                            .with_system(solve_pos)
-                            .with_system(solve_pos_box_box)
-                            .with_system(solve_pos_statics)
-                            .with_system(solve_pos_static_boxes)
-                            .with_system(solve_pos_static_box_box),
+                    )
+                    .with_system(
+                        solve_joints
+                            .label(Step::SolvePositions)
+                            .label(Step::SolveJoints)
+                            .after(Step::CollectJoints)
+                            .after(Step::BuildIslands)
+                    )
+                    .with_system(
+                        break_joints
+                            .after(Step::SolveJoints)
+                    )
+                    .with_system(
+                        solve_pos
+                            .label(Step::SolvePositions)
+                            .after(Step::SolveJoints)
                     )
                     .with_system_set(
                         SystemSet::new()
@@ -566,18 +1023,427 @@ impl Plugin for XPBDPlugin {
                             .with_system(update_vel)
                             .with_system(update_ang_vel)
                     )
-                    .with_system_set(
-                        SystemSet::new()
+                    .with_system(
+                        solve_vel
                             .label(Step::SolveVelocities)
                             .after(Step::UpdateVelocities)
-                            .with_system(solve_vel)
-                            .with_system(solve_vel_statics)
                     )
                     .with_system(
                         sync_transforms
                             .with_run_criteria(last_substep)
                             .after(Step::SolveVelocities),
+                    )
+                    .with_system(
+                        update_sleeping
+                            .with_run_criteria(last_substep)
+                            .label(Step::UpdateSleeping)
+                            .after(Step::SolveVelocities),
+                    )
+                    .with_system(
+                        clear_external_forces
+                            .with_run_criteria(last_substep)
+                            .after(Step::UpdateSleeping),
                     ),
-            );
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_transforms.before(TransformSystem::TransformPropagate),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, remove_static_aabbs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins).add_plugin(XPBDPlugin);
+        app
+    }
+
+    /// Runs one physics step, whatever the wall clock time between updates
+    fn step(app: &mut App) {
+        let delta_time = app.world.get_resource::<PhysicsSettings>().unwrap().delta_time;
+        let mut state = app.world.get_resource_mut::<LoopState>().unwrap();
+        state.accumulator += delta_time;
+        // Keeps the frame's wall clock time out of the accumulator
+        state.has_added_time = true;
+        app.update();
+    }
+
+    fn spawn_floor(app: &mut App) -> Entity {
+        app.world
+            .spawn()
+            .insert_bundle(StaticBoxBundle {
+                pos: Pos(Vec2::new(0., -1.)),
+                collider: Collider::Box(BoxCollider { size: Vec2::new(20., 1.) }),
+                ..Default::default()
+            })
+            .id()
+    }
+
+    #[test]
+    fn ray_hits_dynamic_and_static_bodies() {
+        let mut app = app();
+        let floor = spawn_floor(&mut app);
+        let body = app
+            .world
+            .spawn()
+            .insert_bundle(DynamicBoxBundle::new_with_pos_and_vel(Vec2::new(3., 0.), Vec2::ZERO))
+            .id();
+        step(&mut app);
+
+        let mut state = bevy::ecs::system::SystemState::<SpatialQuery>::new(&mut app.world);
+        let hits = state.get_mut(&mut app.world).ray(Vec2::new(3., 10.), -Vec2::Y, 20.);
+        assert_eq!(hits.iter().map(|(entity, _)| *entity).collect::<Vec<_>>(), vec![body, floor]);
+    }
+
+    #[test]
+    fn despawned_static_leaves_tree_without_step() {
+        let mut app = app();
+        let floor = spawn_floor(&mut app);
+        let wall = spawn_floor(&mut app);
+        let body = app
+            .world
+            .spawn()
+            .insert_bundle(DynamicBoxBundle::new_with_pos_and_vel(Vec2::ZERO, Vec2::ZERO))
+            .id();
+        for _ in 0..5 {
+            step(&mut app);
+        }
+        assert!(app.world.get_resource::<Contacts>().unwrap().0.iter().any(|contact| contact.entity_b == floor));
+
+        // A frame too short for a physics step
+        app.world.despawn(floor);
+        app.world.entity_mut(wall).insert(Mass(1.));
+        app.world.get_resource_mut::<LoopState>().unwrap().accumulator = -1.;
+        app.update();
+        assert!(app.world.get_resource::<StaticAabbTree>().unwrap().0.is_empty());
+
+        app.world.get_resource_mut::<LoopState>().unwrap().accumulator = 0.;
+        step(&mut app);
+        let contacts = &app.world.get_resource::<Contacts>().unwrap().0;
+        assert!(contacts.iter().all(|contact| ![contact.entity_a, contact.entity_b].contains(&floor)));
+        assert!(app.world.get::<Pos>(body).is_some());
+    }
+
+    #[test]
+    fn sliding_box_comes_to_rest() {
+        let mut app = app();
+        spawn_floor(&mut app);
+        let body = app
+            .world
+            .spawn()
+            .insert_bundle(DynamicBoxBundle::new_with_pos_and_vel(Vec2::ZERO, Vec2::new(3., 0.)))
+            .id();
+        for _ in 0..120 {
+            step(&mut app);
+        }
+        // Friction stops it within a few meters, after which it falls asleep and stays put
+        let x = app.world.get::<Pos>(body).unwrap().0.x;
+        assert!(x > 1. && x < 5., "{}", x);
+        for _ in 0..60 {
+            step(&mut app);
+        }
+        assert!(app.world.get::<Sleeping>(body).is_some());
+        let stopped = app.world.get::<Pos>(body).unwrap().0;
+        for _ in 0..30 {
+            step(&mut app);
+        }
+        assert_eq!(app.world.get::<Pos>(body).unwrap().0, stopped);
+    }
+
+    #[test]
+    fn kinematic_platform_pushes_and_carries() {
+        let mut app = app();
+        spawn_floor(&mut app);
+        let platform = app
+            .world
+            .spawn()
+            .insert_bundle(KinematicBundle {
+                collider: Collider::Box(BoxCollider { size: Vec2::new(4., 0.5) }),
+                ..Default::default()
+            })
+            .id();
+        let rider = app
+            .world
+            .spawn()
+            .insert_bundle(DynamicBoxBundle::new_with_pos_and_vel(Vec2::new(0., 0.75), Vec2::ZERO))
+            .id();
+        let pushed = app
+            .world
+            .spawn()
+            .insert_bundle(DynamicBoxBundle::new_with_pos_and_vel(Vec2::new(3., 0.), Vec2::ZERO))
+            .id();
+
+        // Slow enough for friction to keep the rider on board
+        let mut rider_start = Vec2::ZERO;
+        for i in 1..=150 {
+            let target = KinematicTarget {
+                pos: Vec2::new(i as f32 * 0.02, 0.),
+                rot: Rot::ZERO,
+            };
+            app.world.entity_mut(platform).insert(target);
+            step(&mut app);
+            if i == 100 {
+                rider_start = app.world.get::<Pos>(rider).unwrap().0;
+            }
+        }
+        // Once up to speed the rider keeps pace with the platform
+        let rider_travel = app.world.get::<Pos>(rider).unwrap().0 - rider_start;
+        assert!((rider_travel - Vec2::new(1., 0.)).length() < 0.05, "{:?}", rider_travel);
+        for _ in 0..10 {
+            step(&mut app);
+        }
+
+        // The platform ends up exactly where it was sent
+        assert!((app.world.get::<Pos>(platform).unwrap().0 - Vec2::new(3., 0.)).length() < 1e-4);
+        assert!(app.world.get::<Rot>(platform).unwrap().as_radians().abs() < 1e-4);
+        let rider = app.world.get::<Pos>(rider).unwrap().0;
+        assert!(rider.x > 2. && (rider.y - 0.75).abs() < 0.01, "{:?}", rider);
+        let pushed = app.world.get::<Pos>(pushed).unwrap().0;
+        assert!(pushed.x > 5.4, "{:?}", pushed);
+    }
+
+    fn floating_box(app: &mut App) -> Entity {
+        app.insert_resource(Gravity(Vec2::ZERO));
+        app.world.spawn().insert_bundle(DynamicBoxBundle::default()).id()
+    }
+
+    #[test]
+    fn persistent_force_accelerates() {
+        let mut app = app();
+        let body = floating_box(&mut app);
+        app.world.entity_mut(body).insert(ExternalForce::persistent(Vec2::new(2., 0.)));
+        for _ in 0..30 {
+            step(&mut app);
+        }
+        // 2 N on 1 kg for half a second
+        assert!((app.world.get::<Vel>(body).unwrap().0 - Vec2::new(1., 0.)).length() < 1e-3);
+        assert_eq!(app.world.get::<ExternalForce>(body).unwrap().force, Vec2::new(2., 0.));
+    }
+
+    #[test]
+    fn force_cleared_after_step() {
+        let mut app = app();
+        let body = floating_box(&mut app);
+        app.world.entity_mut(body).insert(ExternalForce::new(Vec2::new(60., 0.)));
+        app.world.entity_mut(body).insert(ExternalTorque::new(6.));
+        step(&mut app);
+        assert!((app.world.get::<Vel>(body).unwrap().0 - Vec2::new(1., 0.)).length() < 1e-4);
+        let ang_vel = app.world.get::<AngVel>(body).unwrap().0;
+        assert!(ang_vel > 0.);
+        assert_eq!(app.world.get::<ExternalForce>(body).unwrap().force, Vec2::ZERO);
+        assert_eq!(app.world.get::<ExternalTorque>(body).unwrap().torque, 0.);
+
+        step(&mut app);
+        assert!((app.world.get::<Vel>(body).unwrap().0 - Vec2::new(1., 0.)).length() < 1e-4);
+        assert!((app.world.get::<AngVel>(body).unwrap().0 - ang_vel).abs() < 1e-4);
+    }
+
+    #[test]
+    fn off_center_impulse_spins() {
+        let mut app = app();
+        let body = floating_box(&mut app);
+        let inertia_inv = app.world.get::<Inertia>(body).unwrap().inv;
+        let impulse = ExternalImpulse::at_point(Vec2::new(0., 1.), Vec2::new(0.5, 0.), Vec2::ZERO);
+        app.world.entity_mut(body).insert(impulse);
+        step(&mut app);
+        assert!((app.world.get::<Vel>(body).unwrap().0 - Vec2::new(0., 1.)).length() < 1e-4);
+        assert!((app.world.get::<AngVel>(body).unwrap().0 - 0.5 * inertia_inv).abs() < 1e-4);
+        let ExternalImpulse { impulse, angular_impulse, .. } = *app.world.get::<ExternalImpulse>(body).unwrap();
+        assert_eq!((impulse, angular_impulse), (Vec2::ZERO, 0.));
+    }
+
+    #[test]
+    fn damping_independent_of_substeps() {
+        for num_substeps in [1, 5, 20] {
+            let mut app = app();
+            app.insert_resource(PhysicsSettings {
+                num_substeps,
+                linear_damping: 0.5,
+                angular_damping: 0.5,
+                ..Default::default()
+            });
+            app.insert_resource(Gravity(Vec2::ZERO));
+            let spawn = |app: &mut App, y| {
+                let mut bundle = DynamicBoxBundle::new_with_pos_and_vel(Vec2::new(0., y), Vec2::new(2., 0.));
+                bundle.ang_vel = AngVel(2.);
+                app.world.spawn().insert_bundle(bundle).id()
+            };
+            let default = spawn(&mut app, 0.);
+            let overridden = spawn(&mut app, 5.);
+            app.world.entity_mut(overridden).insert(LinearDamping(2.)).insert(AngularDamping(1.));
+            for _ in 0..60 {
+                step(&mut app);
+            }
+
+            // One second in. Velocities are derived from the tiny moves of each substep, which
+            // costs some precision with many substeps.
+            let vel = |entity| app.world.get::<Vel>(entity).unwrap().0.x;
+            let ang_vel = |entity| app.world.get::<AngVel>(entity).unwrap().0;
+            let close = |value: f32, expected: f32| (value - expected).abs() < 0.01 * expected;
+            assert!(close(vel(default), 2. * (-0.5f32).exp()), "{}: {}", num_substeps, vel(default));
+            assert!(close(ang_vel(default), 2. * (-0.5f32).exp()), "{}: {}", num_substeps, ang_vel(default));
+            assert!(close(vel(overridden), 2. * (-2f32).exp()), "{}: {}", num_substeps, vel(overridden));
+            assert!(close(ang_vel(overridden), 2. * (-1f32).exp()), "{}: {}", num_substeps, ang_vel(overridden));
+        }
+    }
+
+    #[test]
+    fn transforms_interpolated_between_steps() {
+        let mut app = app();
+        app.insert_resource(PhysicsSettings {
+            interpolate_transforms: true,
+            ..Default::default()
+        });
+        let body = floating_box(&mut app);
+        app.world.entity_mut(body).insert(Vel(Vec2::new(1., 0.))).insert(AngVel(1.)).insert(Transform::default());
+        step(&mut app);
+
+        // Half a step left over: halfway between the poses after the first and second step
+        let delta_time = PhysicsSettings::default().delta_time;
+        let mut state = app.world.get_resource_mut::<LoopState>().unwrap();
+        state.accumulator += 1.5 * delta_time;
+        state.has_added_time = true;
+        app.update();
+
+        let transform = app.world.get::<Transform>(body).unwrap();
+        assert!((transform.translation.x - 1.5 * delta_time).abs() < 1e-5, "{}", transform.translation.x);
+        let rotation = Quat::from(Rot::from_radians(1.5 * delta_time));
+        assert!(transform.rotation.abs_diff_eq(rotation, 1e-5), "{:?}", transform.rotation);
+        assert!((app.world.get::<Pos>(body).unwrap().0.x - 2. * delta_time).abs() < 1e-5);
+    }
+
+    #[test]
+    fn settings_retuned_between_frames() {
+        #[derive(Default)]
+        struct Substeps(u32);
+        fn count_substeps(mut substeps: ResMut<Substeps>) {
+            substeps.0 += 1;
+        }
+
+        let mut app = app();
+        app.init_resource::<Substeps>().add_system_to_stage(FixedUpdateStage, count_substeps);
+        let body = floating_box(&mut app);
+        app.world.entity_mut(body).insert(Vel(Vec2::new(1., 0.)));
+        let run = |app: &mut App, delta_time: f32, num_substeps: u32| {
+            let mut settings = app.world.get_resource_mut::<PhysicsSettings>().unwrap();
+            settings.delta_time = delta_time;
+            settings.num_substeps = num_substeps;
+            app.world.get_resource_mut::<Substeps>().unwrap().0 = 0;
+            let start = app.world.get::<Pos>(body).unwrap().0.x;
+            step(app);
+            let travel = app.world.get::<Pos>(body).unwrap().0.x - start;
+            (travel, app.world.get_resource::<Substeps>().unwrap().0)
+        };
+
+        let (travel, substeps) = run(&mut app, 1. / 60., 10);
+        assert!((travel - 1. / 60.).abs() < 1e-5);
+        assert_eq!(substeps, 10);
+        let (travel, substeps) = run(&mut app, 1. / 30., 3);
+        assert!((travel - 1. / 30.).abs() < 1e-5);
+        assert_eq!(substeps, 3);
+
+        // No stepping at all while the step has no length, and no half finished step afterwards
+        let (travel, substeps) = run(&mut app, 0., 3);
+        assert_eq!((travel, substeps), (0., 0));
+        assert!(!app.world.get_resource::<LoopState>().unwrap().substepping);
+        app.world.get_resource_mut::<LoopState>().unwrap().accumulator = 0.;
+        let (travel, substeps) = run(&mut app, 1. / 60., 2);
+        assert!((travel - 1. / 60.).abs() < 1e-5);
+        assert_eq!(substeps, 2);
+    }
+
+    #[test]
+    fn push_wakes_sleeping_body() {
+        let mut app = app();
+        spawn_floor(&mut app);
+        let body = app
+            .world
+            .spawn()
+            .insert_bundle(DynamicBoxBundle::new_with_pos_and_vel(Vec2::ZERO, Vec2::ZERO))
+            .id();
+        for _ in 0..120 {
+            step(&mut app);
+        }
+        assert!(app.world.get::<Sleeping>(body).is_some());
+
+        let start = app.world.get::<Pos>(body).unwrap().0;
+        app.world.entity_mut(body).insert(ExternalForce::new(Vec2::new(600., 0.)));
+        for _ in 0..3 {
+            step(&mut app);
+        }
+        assert!(app.world.get::<Sleeping>(body).is_none());
+        assert!(app.world.get::<Pos>(body).unwrap().0.x - start.x > 0.01);
+        assert_eq!(app.world.get::<ExternalForce>(body).unwrap().force, Vec2::ZERO);
+    }
+
+    #[test]
+    fn persistent_force_keeps_body_awake() {
+        let mut app = app();
+        spawn_floor(&mut app);
+        let body = app
+            .world
+            .spawn()
+            .insert_bundle(DynamicBoxBundle::new_with_pos_and_vel(Vec2::ZERO, Vec2::ZERO))
+            .id();
+        for _ in 0..120 {
+            step(&mut app);
+        }
+        assert!(app.world.get::<Sleeping>(body).is_some());
+
+        // Too weak to overcome static friction, the box stays put but must not doze off again
+        app.world.entity_mut(body).insert(ExternalForce::persistent(Vec2::new(1., 0.)));
+        for _ in 0..120 {
+            step(&mut app);
+            assert!(app.world.get::<Sleeping>(body).is_none());
+        }
+
+        app.world.entity_mut(body).remove::<ExternalForce>();
+        for _ in 0..120 {
+            step(&mut app);
+        }
+        assert!(app.world.get::<Sleeping>(body).is_some());
+
+        app.world.entity_mut(body).insert(ExternalTorque::persistent(0.1));
+        for _ in 0..120 {
+            step(&mut app);
+            assert!(app.world.get::<Sleeping>(body).is_none());
+        }
+    }
+
+    #[test]
+    fn jointed_bodies_do_not_collide() {
+        // Two overlapping limbs pinned at the elbow
+        let elbow = |collide_connected| {
+            let mut app = app();
+            app.insert_resource(Gravity(Vec2::ZERO));
+            let upper = app.world.spawn().insert_bundle(DynamicBoxBundle::default()).id();
+            let lower = app
+                .world
+                .spawn()
+                .insert_bundle(DynamicBoxBundle::new_with_pos_and_vel(Vec2::new(0.5, 0.), Vec2::ZERO))
+                .id();
+            app.world.spawn().insert(RevoluteJoint {
+                collide_connected,
+                ..RevoluteJoint::new(upper, lower, Vec2::new(0.25, 0.), Vec2::new(-0.25, 0.))
+            });
+            (app, upper, lower)
+        };
+
+        let (mut app, upper, lower) = elbow(false);
+        for _ in 0..10 {
+            step(&mut app);
+        }
+        assert!(app.world.get_resource::<Contacts>().unwrap().0.is_empty());
+        let pos = |entity| app.world.get::<Pos>(entity).unwrap().0;
+        assert!((pos(upper).distance(pos(lower)) - 0.5).abs() < 0.001);
+
+        let (mut app, ..) = elbow(true);
+        step(&mut app);
+        assert!(!app.world.get_resource::<Contacts>().unwrap().0.is_empty());
     }
 }
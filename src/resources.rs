@@ -1,23 +1,49 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::contact::Contact;
+use crate::islands::UnionFind;
+use crate::{GravityAttractor, GravityZone, Rot, SleepThreshold};
 
 #[derive(Debug, Clone)]
 pub struct BodyContact {
     pub entity_a: Entity,
     pub entity_b: Entity,
+    /// Contact point on body a, in the local frame of body a
+    pub local_r_a: Vec2,
+    /// Contact point on body b, in the local frame of body b
+    pub local_r_b: Vec2,
+    /// World space arm from the center of body a to its contact point, as of the last position solve
     pub r_a: Vec2,
+    /// World space arm from the center of body b to its contact point, as of the last position solve
     pub r_b: Vec2,
+    /// Points from body a towards body b
     pub normal: Vec2,
+    /// Magnitude of the normal positional impulse, zero when the contact was not active
+    pub normal_lagrange: f32,
 }
 
-#[derive(Default, Debug)]
-pub struct Contacts(pub Vec<BodyContact>);
+impl BodyContact {
+    pub(crate) fn new(entity_a: Entity, entity_b: Entity, rot_a: Rot, rot_b: Rot, contact: Contact) -> Self {
+        Self {
+            entity_a,
+            entity_b,
+            local_r_a: rot_a.inv().rotate(contact.r_a),
+            local_r_b: rot_b.inv().rotate(contact.r_b),
+            r_a: contact.r_a,
+            r_b: contact.r_b,
+            normal: contact.normal,
+            normal_lagrange: 0.,
+        }
+    }
+}
 
 #[derive(Default, Debug)]
-pub struct StaticContacts(pub Vec<(Entity, Entity, Vec2)>);
+pub struct Contacts(pub Vec<BodyContact>);
 
 #[derive(Debug, Default)]
 pub(crate) struct CollisionPairs(pub Vec<(Entity, Entity)>);
 
+/// Gravity acting on every body, on top of any `GravityAttractor` or `GravityZone`
 #[derive(Debug)]
 pub struct Gravity(pub Vec2);
 
@@ -25,4 +51,272 @@ impl Default for Gravity {
     fn default() -> Self {
         Self(Vec2::new(0., -9.81))
     }
-}
\ No newline at end of file
+}
+
+/// The islands that were put to sleep, so touching one body wakes all of them
+#[derive(Debug, Default)]
+pub(crate) struct SleepingIslands {
+    next_id: u32,
+    islands: HashMap<u32, Vec<Entity>>,
+    island_of: HashMap<Entity, u32>,
+}
+
+impl SleepingIslands {
+    pub fn island_of(&self, entity: Entity) -> Option<u32> {
+        self.island_of.get(&entity).copied()
+    }
+
+    /// Records a new island, absorbing the sleeping islands in `merged`
+    pub fn sleep(&mut self, mut members: Vec<Entity>, merged: &[u32]) -> u32 {
+        for island in merged {
+            members.extend(self.islands.remove(island).unwrap_or_default());
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        for &entity in members.iter() {
+            self.island_of.insert(entity, id);
+        }
+        self.islands.insert(id, members);
+        id
+    }
+
+    /// Forgets the island and returns its bodies
+    pub fn wake(&mut self, island: u32) -> Vec<Entity> {
+        let members = self.islands.remove(&island).unwrap_or_default();
+        for entity in members.iter() {
+            self.island_of.remove(entity);
+        }
+        members
+    }
+
+    pub fn ids(&self) -> Vec<u32> {
+        self.islands.keys().copied().collect()
+    }
+
+    /// Sleeping islands with a body linked to a `moving` one. Kinematic bodies are never in
+    /// contact with sleeping bodies, so `links` should include the collision pairs.
+    pub fn touched(
+        &self,
+        links: impl Iterator<Item = (Entity, Entity)>,
+        moving: impl Fn(Entity) -> bool,
+    ) -> Vec<u32> {
+        let mut touched = Vec::new();
+        for (entity_a, entity_b) in links {
+            for (sleeping, other) in [(entity_a, entity_b), (entity_b, entity_a)] {
+                if let Some(island) = self.island_of(sleeping) {
+                    if moving(other) {
+                        touched.push(island);
+                    }
+                }
+            }
+        }
+        touched
+    }
+
+    /// Groups the awake bodies in `ready` into islands over their links and returns the islands
+    /// whose bodies are all ready to sleep. Each comes with the sleeping islands it rests on,
+    /// which sleep on as one with it.
+    pub fn resting(
+        &self,
+        ready: &HashMap<Entity, bool>,
+        links: &[(Entity, Entity)],
+    ) -> Vec<(Vec<Entity>, Vec<u32>)> {
+        let mut awake: Vec<Entity> = ready.keys().copied().collect();
+        awake.sort();
+        let index: HashMap<Entity, usize> = awake.iter().enumerate().map(|(i, entity)| (*entity, i)).collect();
+        let mut union_find = UnionFind::new(awake.len());
+        for (entity_a, entity_b) in links.iter() {
+            if let (Some(&a), Some(&b)) = (index.get(entity_a), index.get(entity_b)) {
+                union_find.union(a, b);
+            }
+        }
+
+        // Sleeping islands touched by each group, keyed by the group's root
+        let mut touched: HashMap<usize, Vec<u32>> = HashMap::default();
+        for (entity_a, entity_b) in links.iter() {
+            let (awake_index, other) = match (index.get(entity_a), index.get(entity_b)) {
+                (Some(&a), None) => (a, *entity_b),
+                (None, Some(&b)) => (b, *entity_a),
+                _ => continue,
+            };
+            if let Some(island) = self.island_of(other) {
+                touched.entry(union_find.find(awake_index)).or_default().push(island);
+            }
+        }
+
+        let mut resting = Vec::new();
+        for group in union_find.groups() {
+            if !group.iter().all(|&i| ready[&awake[i]]) {
+                continue;
+            }
+            let members: Vec<Entity> = group.iter().map(|&i| awake[i]).collect();
+            let mut merged = touched.remove(&union_find.find(group[0])).unwrap_or_default();
+            merged.sort_unstable();
+            merged.dedup();
+            resting.push((members, merged));
+        }
+        resting
+    }
+}
+
+/// The gravity attractors and zones of the current substep, with their poses
+#[derive(Debug, Default)]
+pub(crate) struct GravitySources {
+    pub attractors: Vec<(Entity, Vec2, GravityAttractor)>,
+    pub zones: Vec<(Vec2, Rot, GravityZone)>,
+}
+
+impl GravitySources {
+    /// Acceleration from the attractors and zones on a body at `pos`. An attractor does not
+    /// pull on its own entity.
+    pub fn acceleration(&self, entity: Entity, pos: Vec2) -> Vec2 {
+        let mut acceleration = Vec2::ZERO;
+        for (attractor_entity, attractor_pos, attractor) in self.attractors.iter() {
+            let delta = *attractor_pos - pos;
+            let distance = delta.length();
+            if *attractor_entity == entity || distance <= f32::EPSILON {
+                continue;
+            }
+            let falloff_distance = distance.max(attractor.min_distance);
+            acceleration += delta / distance * attractor.strength / (falloff_distance * falloff_distance);
+        }
+        for (zone_pos, zone_rot, zone) in self.zones.iter() {
+            let local = zone_rot.inv().rotate(pos - *zone_pos).abs();
+            if local.x <= zone.size.x / 2. && local.y <= zone.size.y / 2. {
+                acceleration += zone.gravity;
+            }
+        }
+        acceleration
+    }
+}
+
+/// Order in which the solver goes through the constraints of an island. Independent islands
+/// are solved in parallel either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConstraintOrder {
+    /// One constraint after the other, each seeing the corrections of the ones before
+    #[default]
+    GaussSeidel,
+    /// Constraints are grouped into colours whose constraints share no dynamic body, and the
+    /// constraints of one colour are solved in parallel. Pays off for large piles; the result
+    /// differs from `GaussSeidel` but is just as deterministic.
+    GraphColoring,
+}
+
+/// Timestep of the simulation. It is read every frame, so it can be tuned at runtime.
+#[derive(Debug, Clone)]
+pub struct PhysicsSettings {
+    /// Length of one physics step in seconds. The simulation does not step while it is not
+    /// positive.
+    pub delta_time: f32,
+    /// Number of substeps each step is split into. Zero counts as one.
+    pub num_substeps: u32,
+    /// Blend each `Transform` between the last two physics steps by the time left over in the
+    /// accumulator, instead of snapping it to the latest step. Smooths out motion when the
+    /// display rate differs from the physics rate, at the cost of one step of latency.
+    pub interpolate_transforms: bool,
+    /// Damping of bodies without a `LinearDamping`
+    pub linear_damping: f32,
+    /// Damping of bodies without an `AngularDamping`
+    pub angular_damping: f32,
+    /// Put contact islands to sleep once all their bodies have been resting for `time_to_sleep`
+    pub allow_sleeping: bool,
+    pub time_to_sleep: f32,
+    /// Threshold of bodies without a `SleepThreshold`
+    pub sleep_threshold: SleepThreshold,
+    pub constraint_order: ConstraintOrder,
+}
+
+impl PhysicsSettings {
+    /// Length of one substep in seconds
+    pub fn sub_dt(&self) -> f32 {
+        self.delta_time / self.substeps() as f32
+    }
+
+    /// `num_substeps`, at least one
+    pub(crate) fn substeps(&self) -> u32 {
+        self.num_substeps.max(1)
+    }
+
+    /// Factor a velocity is scaled by over one substep for a damping `rate`. It compounds to
+    /// `exp(-rate * t)` over time `t`, whatever the step size.
+    pub(crate) fn damping_factor(&self, rate: f32) -> f32 {
+        (-rate * self.sub_dt()).exp()
+    }
+}
+
+impl Default for PhysicsSettings {
+    fn default() -> Self {
+        Self {
+            delta_time: 1. / 60.,
+            num_substeps: 10,
+            interpolate_transforms: false,
+            linear_damping: 0.,
+            angular_damping: 0.,
+            allow_sleeping: true,
+            time_to_sleep: 0.5,
+            sleep_threshold: Default::default(),
+            constraint_order: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gravity_sources() {
+        let planet = Entity::from_raw(0);
+        let body = Entity::from_raw(1);
+        let sources = GravitySources {
+            attractors: vec![(planet, Vec2::ZERO, GravityAttractor { strength: 8., min_distance: 1. })],
+            zones: vec![(
+                Vec2::new(0., 10.),
+                Rot::from_degrees(90.),
+                GravityZone { size: Vec2::new(2., 8.), gravity: Vec2::X },
+            )],
+        };
+        // Inverse square falloff, capped inside the minimum distance
+        assert!((sources.acceleration(body, Vec2::new(2., 0.)) - Vec2::new(-2., 0.)).length() < 0.0001);
+        assert!((sources.acceleration(body, Vec2::new(0., -0.5)) - Vec2::new(0., 8.)).length() < 0.0001);
+        assert_eq!(sources.acceleration(planet, Vec2::ZERO), Vec2::ZERO);
+        // The zone is turned sideways, so it is 8 wide and 2 high
+        let in_zone = sources.acceleration(body, Vec2::new(3., 10.));
+        assert!((in_zone - (Vec2::X + Vec2::new(-3., -10.).normalize() * 8. / 109.)).length() < 0.0001);
+        assert!((sources.acceleration(body, Vec2::new(0., 11.5)) - Vec2::new(0., -8. / 11.5 / 11.5)).length() < 0.0001);
+    }
+
+    #[test]
+    fn sleeping_islands() {
+        let [a, b, c, d, e] = [0, 1, 2, 3, 4].map(Entity::from_raw);
+        let mut islands = SleepingIslands::default();
+        let pile = islands.sleep(vec![a], &[]);
+
+        // b rests on the sleeping a, c and d are linked but d is still moving, e is on its own
+        let ready: HashMap<Entity, bool> = [(b, true), (c, true), (d, false), (e, true)].into_iter().collect();
+        let links = [(b, a), (c, d)];
+        let resting = islands.resting(&ready, &links);
+        assert_eq!(resting, vec![(vec![b], vec![pile]), (vec![e], vec![])]);
+
+        let merged = islands.sleep(vec![b], &[pile]);
+        assert_eq!(islands.island_of(a), Some(merged));
+        assert!(islands.touched(links.into_iter(), |entity| entity == d).is_empty());
+        assert_eq!(islands.touched([(c, a)].into_iter(), |entity| entity == c), vec![merged]);
+
+        let mut woken = islands.wake(merged);
+        woken.sort();
+        assert_eq!(woken, vec![a, b]);
+        assert_eq!(islands.island_of(a), None);
+    }
+
+    #[test]
+    fn zero_substeps() {
+        let settings = PhysicsSettings {
+            num_substeps: 0,
+            ..Default::default()
+        };
+        assert_eq!(settings.substeps(), 1);
+        assert_eq!(settings.sub_dt(), settings.delta_time);
+    }
+}
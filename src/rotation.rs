@@ -63,6 +63,11 @@ impl Rot {
     pub fn cos(self) -> f32 {
         self.cos
     }
+
+    /// Rotation a fraction `t` of the way from `self` to `to`, turning the short way round
+    pub fn slerp(self, to: Rot, t: f32) -> Self {
+        self.mul(Rot::from_radians(self.inv().mul(to).as_radians() * t))
+    }
 }
 
 impl Add<Self> for Rot {
@@ -112,5 +117,37 @@ impl Distribution<Rot> for Standard {
 #[derive(Component, Debug, Default)]
 pub struct PrevRot(pub Rot);
 
+/// Rotation at the start of the last physics step, see `PhysicsSettings::interpolate_transforms`
+#[derive(Component, Debug, Default)]
+pub struct PrevStepRot(pub Rot);
+
 #[derive(Component, Debug, Default)]
-pub struct AngVel(pub(crate) f32);
\ No newline at end of file
+pub struct AngVel(pub(crate) f32);
+
+impl AngVel {
+    pub fn new(ang_vel: f32) -> Self {
+        Self(ang_vel)
+    }
+
+    pub fn get(&self) -> f32 {
+        self.0
+    }
+
+    /// Takes effect from the next step on
+    pub fn set(&mut self, ang_vel: f32) {
+        self.0 = ang_vel;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slerp_short_way_across_pi() {
+        let from = Rot::from_degrees(170.);
+        let to = Rot::from_degrees(-170.);
+        assert!((from.slerp(to, 0.5).cos() + 1.).abs() < 1e-5);
+        assert!((from.slerp(to, 0.25).as_radians() - 175f32.to_radians()).abs() < 1e-5);
+        assert!((to.slerp(from, 0.25).as_radians() + 175f32.to_radians()).abs() < 1e-5);
+    }
+}
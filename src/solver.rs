@@ -0,0 +1,550 @@
+use bevy::{prelude::*, tasks::TaskPool};
+
+use crate::{
+    islands::{ConstraintIslands, Island},
+    BodyContact, ConstraintOrder, Inertia, Mass, Rot, Sleeping,
+};
+
+/// Copy of the state of a body that the solver works on, so that islands can be solved
+/// without borrowing the ECS
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SolverBody {
+    pub pos: Vec2,
+    pub rot: Rot,
+    /// `None` for bodies that did not move this substep, e.g. static or sleeping ones
+    pub prev_pos: Option<Vec2>,
+    pub prev_rot: Option<Rot>,
+    pub vel: Vec2,
+    pub ang_vel: f32,
+    pub pre_solve_vel: Vec2,
+    pub pre_solve_ang_vel: f32,
+    pub mass_inv: f32,
+    pub inertia_inv: f32,
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+    pub restitution: f32,
+}
+
+impl SolverBody {
+    /// Whether the solver may move this body, i.e. whether it has to be written back
+    pub fn is_dynamic(&self) -> bool {
+        self.mass_inv != 0. || self.inertia_inv != 0.
+    }
+
+    /// Inverse mass of the body as seen along `n` from a point at arm `r` from its center of mass
+    pub fn generalized_inverse_mass(&self, r: Vec2, n: Vec2) -> f32 {
+        self.mass_inv + self.inertia_inv * r.perp_dot(n).powi(2)
+    }
+
+    /// Applies a positional impulse at arm `r`, moving and rotating the body accordingly
+    pub fn apply_positional_impulse(&mut self, r: Vec2, impulse: Vec2) {
+        if !self.is_dynamic() {
+            return;
+        }
+        self.pos += impulse * self.mass_inv;
+        self.rot = self.rot.mul(Rot::from_radians(self.inertia_inv * r.perp_dot(impulse)));
+    }
+
+    /// Applies an angular positional impulse, rotating the body only
+    pub fn apply_angular_impulse(&mut self, impulse: f32) {
+        if !self.is_dynamic() {
+            return;
+        }
+        self.rot = self.rot.mul(Rot::from_radians(self.inertia_inv * impulse));
+    }
+
+    /// Position at the start of the substep
+    pub fn start_pos(&self) -> Vec2 {
+        self.prev_pos.unwrap_or(self.pos)
+    }
+
+    /// Rotation at the start of the substep
+    pub fn start_rot(&self) -> Rot {
+        self.prev_rot.unwrap_or(self.rot)
+    }
+
+    /// Applies a velocity impulse at arm `r`, changing both linear and angular velocity
+    pub fn apply_velocity_impulse(&mut self, r: Vec2, impulse: Vec2) {
+        self.vel += impulse * self.mass_inv;
+        self.ang_vel += self.inertia_inv * r.perp_dot(impulse);
+    }
+
+    /// Velocity of the point at arm `r`
+    pub fn point_velocity(&self, r: Vec2) -> Vec2 {
+        self.vel + self.ang_vel * r.perp()
+    }
+
+    /// Velocity of the point at arm `r` before the position solve
+    pub fn pre_solve_point_velocity(&self, r: Vec2) -> Vec2 {
+        self.pre_solve_vel + self.pre_solve_ang_vel * r.perp()
+    }
+
+    /// Displacement of the point at arm `r` since the start of the substep
+    pub fn point_displacement(&self, r: Vec2) -> Vec2 {
+        let linear = self.prev_pos.map_or(Vec2::ZERO, |prev_pos| self.pos - prev_pos);
+        let angular = self.prev_rot.map_or(Vec2::ZERO, |prev_rot| r - prev_rot.rotate(self.rot.inv().rotate(r)));
+        linear + angular
+    }
+}
+
+/// Inverse mass and inverse inertia of a body, both zero for bodies without a `Mass` and for
+/// sleeping bodies
+pub(crate) fn inverse_mass(mass: Option<&Mass>, inertia: Option<&Inertia>, sleeping: Option<&Sleeping>) -> (f32, f32) {
+    match (mass, inertia, sleeping) {
+        (_, _, Some(_)) | (None, _, _) => (0., 0.),
+        (Some(mass), Some(inertia), None) => (1. / mass.0, inertia.inv),
+        (Some(mass), None, None) => (1. / mass.0, 0.),
+    }
+}
+
+/// Mutable access to two different bodies of an island
+pub(crate) fn pair_mut<T>(items: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert!(a != b);
+    if a < b {
+        let (head, tail) = items.split_at_mut(b);
+        (&mut head[a], &mut tail[0])
+    } else {
+        let (head, tail) = items.split_at_mut(a);
+        (&mut tail[0], &mut head[b])
+    }
+}
+
+/// Copies the bodies of every island for the solver
+pub(crate) fn gather_bodies(islands: &ConstraintIslands, body: impl Fn(Entity) -> SolverBody) -> Vec<Vec<SolverBody>> {
+    islands
+        .islands
+        .iter()
+        .map(|island| island.bodies.iter().map(|&entity| body(entity)).collect())
+        .collect()
+}
+
+/// Hands the solved bodies that the solver may have moved back to `f`, island by island
+pub(crate) fn scatter_bodies(
+    islands: &ConstraintIslands,
+    solved: Vec<Vec<SolverBody>>,
+    mut f: impl FnMut(Entity, SolverBody),
+) {
+    for (island, solved) in islands.islands.iter().zip(solved) {
+        for (&entity, body) in island.bodies.iter().zip(solved) {
+            if body.is_dynamic() {
+                f(entity, body);
+            }
+        }
+    }
+}
+
+/// Solves the constraints of all islands in the given order, returning the solved bodies
+pub(crate) fn solve_constraints<T: Clone + Send + Sync + 'static>(
+    pool: &TaskPool,
+    islands: &ConstraintIslands,
+    order: ConstraintOrder,
+    bodies: Vec<Vec<SolverBody>>,
+    constraints: &mut [T],
+    solve: impl Fn(&mut T, &mut SolverBody, &mut SolverBody) + Sync,
+) -> Vec<Vec<SolverBody>> {
+    match order {
+        ConstraintOrder::GaussSeidel => solve_islands(pool, &islands.islands, bodies, constraints, solve),
+        ConstraintOrder::GraphColoring => solve_colors(pool, islands, bodies, constraints, solve),
+    }
+}
+
+/// Solves every island on the compute task pool, each on its own copy of its bodies. Islands
+/// share no dynamic bodies, so the result does not depend on the number of threads.
+fn solve_islands<T: Send + Sync>(
+    pool: &TaskPool,
+    islands: &[Island],
+    bodies: Vec<Vec<SolverBody>>,
+    constraints: &mut [T],
+    solve: impl Fn(&mut T, &mut SolverBody, &mut SolverBody) + Sync,
+) -> Vec<Vec<SolverBody>> {
+    let mut work = Vec::with_capacity(islands.len());
+    let mut rest = constraints;
+    for (island, bodies) in islands.iter().zip(bodies) {
+        let (island_constraints, tail) = rest.split_at_mut(island.constraints.len());
+        rest = tail;
+        work.push((island, bodies, island_constraints));
+    }
+
+    let threads = pool.thread_num().max(1);
+    let batch_size = work.len().div_ceil(threads);
+    let solve = &solve;
+    pool.scope(|scope| {
+        let mut work = work.into_iter();
+        loop {
+            let batch: Vec<_> = work.by_ref().take(batch_size).collect();
+            if batch.is_empty() {
+                break;
+            }
+            scope.spawn(async move {
+                batch
+                    .into_iter()
+                    .map(|(island, mut bodies, constraints)| {
+                        for (constraint, &(a, b)) in constraints.iter_mut().zip(island.constraint_bodies.iter()) {
+                            let (body_a, body_b) = pair_mut(&mut bodies, a, b);
+                            solve(constraint, body_a, body_b);
+                        }
+                        bodies
+                    })
+                    .collect::<Vec<_>>()
+            });
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Solves the constraints colour by colour, spreading each colour over the compute task pool.
+/// The constraints of a colour share no dynamic bodies, so each one is solved on copies of its
+/// two bodies and the copies are written back after the whole colour is done.
+fn solve_colors<T: Clone + Send + Sync + 'static>(
+    pool: &TaskPool,
+    islands: &ConstraintIslands,
+    bodies: Vec<Vec<SolverBody>>,
+    constraints: &mut [T],
+    solve: impl Fn(&mut T, &mut SolverBody, &mut SolverBody) + Sync,
+) -> Vec<Vec<SolverBody>> {
+    // The bodies of all islands in one list, with the bodies of the constraints indexing into it
+    let mut pairs = Vec::with_capacity(constraints.len());
+    let mut offset = 0;
+    for (island, island_bodies) in islands.islands.iter().zip(bodies.iter()) {
+        pairs.extend(island.constraint_bodies.iter().map(|&(a, b)| (offset + a, offset + b)));
+        offset += island_bodies.len();
+    }
+    let lens: Vec<usize> = bodies.iter().map(Vec::len).collect();
+    let mut all_bodies: Vec<SolverBody> = bodies.into_iter().flatten().collect();
+
+    let threads = pool.thread_num().max(1);
+    let solve = &solve;
+    for color in islands.colors.iter() {
+        let batch_size = color.len().div_ceil(threads);
+        let (all_bodies_ref, pairs_ref, constraints_ref) = (&all_bodies, &pairs, &*constraints);
+        let solved = pool.scope(|scope| {
+            for batch in color.chunks(batch_size) {
+                scope.spawn(async move {
+                    batch
+                        .iter()
+                        .map(|&i| {
+                            let (a, b) = pairs_ref[i];
+                            let mut constraint = constraints_ref[i].clone();
+                            let (mut body_a, mut body_b) = (all_bodies_ref[a], all_bodies_ref[b]);
+                            solve(&mut constraint, &mut body_a, &mut body_b);
+                            (i, constraint, body_a, body_b)
+                        })
+                        .collect::<Vec<_>>()
+                });
+            }
+        });
+        for (i, constraint, body_a, body_b) in solved.into_iter().flatten() {
+            let (a, b) = pairs[i];
+            constraints[i] = constraint;
+            // Static bodies may be shared within a colour, but are never changed
+            if body_a.is_dynamic() {
+                all_bodies[a] = body_a;
+            }
+            if body_b.is_dynamic() {
+                all_bodies[b] = body_b;
+            }
+        }
+    }
+
+    let mut all_bodies = all_bodies.into_iter();
+    lens.into_iter().map(|len| all_bodies.by_ref().take(len).collect()).collect()
+}
+
+/// How stiff a positional or angular correction is and how hard it may push, over one substep
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PositionalParams {
+    /// Inverse stiffness, zero for a rigid correction
+    pub compliance: f32,
+    pub sub_dt: f32,
+    /// Bound of the magnitude of the impulse
+    pub max_lagrange: f32,
+}
+
+impl PositionalParams {
+    /// A rigid correction of unbounded strength
+    pub fn rigid(sub_dt: f32) -> Self {
+        Self {
+            compliance: 0.,
+            sub_dt,
+            max_lagrange: f32::INFINITY,
+        }
+    }
+
+    pub fn compliant(compliance: f32, sub_dt: f32) -> Self {
+        Self {
+            compliance,
+            ..Self::rigid(sub_dt)
+        }
+    }
+
+    /// The same correction, pushing with at most `max_force` (a torque for angular corrections).
+    /// Over a substep of length `h`, a force `f` corresponds to an impulse of `f h²`.
+    pub fn with_max_force(self, max_force: f32) -> Self {
+        Self {
+            max_lagrange: max_force * self.sub_dt * self.sub_dt,
+            ..self
+        }
+    }
+
+    /// The compliance scaled to the substep
+    fn alpha(&self) -> f32 {
+        self.compliance / (self.sub_dt * self.sub_dt)
+    }
+}
+
+/// Moves the bodies so that the anchors at arms `r_a` and `r_b` close the gap `correction`,
+/// which points from the anchor of body a towards the one of body b. A positive compliance
+/// closes only part of it, like a spring. Returns the magnitude of the positional impulse.
+pub(crate) fn solve_positional(
+    a: &mut SolverBody,
+    b: &mut SolverBody,
+    r_a: Vec2,
+    r_b: Vec2,
+    correction: Vec2,
+    params: PositionalParams,
+) -> f32 {
+    let magnitude = correction.length();
+    if magnitude <= f32::EPSILON {
+        return 0.;
+    }
+    let n = correction / magnitude;
+    let w = a.generalized_inverse_mass(r_a, n) + b.generalized_inverse_mass(r_b, n);
+    let alpha = params.alpha();
+    if w + alpha <= 0. {
+        return 0.;
+    }
+    let lagrange = (magnitude / (w + alpha)).min(params.max_lagrange);
+    a.apply_positional_impulse(r_a, n * lagrange);
+    b.apply_positional_impulse(r_b, -n * lagrange);
+    lagrange
+}
+
+/// Rotates the bodies in opposite directions to undo `angle` of rotation of body b relative to
+/// body a. Returns the signed angular impulse applied to body a.
+pub(crate) fn solve_angular(a: &mut SolverBody, b: &mut SolverBody, angle: f32, params: PositionalParams) -> f32 {
+    let w = a.inertia_inv + b.inertia_inv;
+    let alpha = params.alpha();
+    if angle == 0. || w + alpha <= 0. {
+        return 0.;
+    }
+    let lagrange = (angle / (w + alpha)).clamp(-params.max_lagrange, params.max_lagrange);
+    a.apply_angular_impulse(lagrange);
+    b.apply_angular_impulse(-lagrange);
+    lagrange
+}
+
+/// Static friction: the positional impulse (applied to body a) that cancels the tangential
+/// drift `delta_p` of the contact points, or `None` if it would leave the friction cone
+fn static_friction_impulse(
+    delta_p: Vec2,
+    normal: Vec2,
+    normal_lagrange: f32,
+    static_coefficient: f32,
+    inverse_mass: impl Fn(Vec2) -> f32,
+) -> Option<Vec2> {
+    let delta_p_tangent = delta_p - normal * delta_p.dot(normal);
+    let sliding_len = delta_p_tangent.length();
+    if sliding_len <= f32::EPSILON {
+        return None;
+    }
+    let tangent = delta_p_tangent / sliding_len;
+    let tangent_lagrange = sliding_len / inverse_mass(tangent);
+    if tangent_lagrange < static_coefficient * normal_lagrange {
+        Some(-tangent * tangent_lagrange)
+    } else {
+        None
+    }
+}
+
+/// Dynamic friction: the velocity impulse (applied to body a) opposing the tangential relative
+/// velocity, bounded by the normal force `normal_lagrange / h²`
+fn dynamic_friction_impulse(
+    relative_vel: Vec2,
+    normal: Vec2,
+    normal_lagrange: f32,
+    dynamic_coefficient: f32,
+    sub_dt: f32,
+    inverse_mass: impl Fn(Vec2) -> f32,
+) -> Option<Vec2> {
+    let tangent_vel = relative_vel - normal * relative_vel.dot(normal);
+    let tangent_speed = tangent_vel.length();
+    if tangent_speed <= f32::EPSILON {
+        return None;
+    }
+    let tangent = tangent_vel / tangent_speed;
+    let delta_v = (dynamic_coefficient * normal_lagrange / sub_dt).min(tangent_speed);
+    Some(-tangent * (delta_v / inverse_mass(tangent)))
+}
+
+/// Resolves the penetration of a contact and applies static friction, recording the normal
+/// impulse for the velocity solve
+pub(crate) fn solve_contact_pos(contact: &mut BodyContact, a: &mut SolverBody, b: &mut SolverBody, sub_dt: f32) {
+    contact.normal_lagrange = 0.;
+
+    // Earlier corrections in this substep may have moved the bodies apart already
+    let normal = contact.normal;
+    let r_a = a.rot.rotate(contact.local_r_a);
+    let r_b = b.rot.rotate(contact.local_r_b);
+    contact.r_a = r_a;
+    contact.r_b = r_b;
+    let penetration = Vec2::dot(a.pos + r_a - b.pos - r_b, normal);
+    if penetration <= 0. {
+        return;
+    }
+
+    let normal_lagrange = solve_positional(a, b, r_a, r_b, -normal * penetration, PositionalParams::rigid(sub_dt));
+    if normal_lagrange <= 0. {
+        return;
+    }
+
+    let delta_p = a.point_displacement(r_a) - b.point_displacement(r_b);
+    let static_coefficient = (a.static_friction + b.static_friction) / 2.;
+    if let Some(friction_impulse) = static_friction_impulse(
+        delta_p,
+        normal,
+        normal_lagrange,
+        static_coefficient,
+        |t| a.generalized_inverse_mass(r_a, t) + b.generalized_inverse_mass(r_b, t),
+    ) {
+        a.apply_positional_impulse(r_a, friction_impulse);
+        b.apply_positional_impulse(r_b, -friction_impulse);
+    }
+
+    contact.normal_lagrange = normal_lagrange;
+}
+
+/// Applies restitution and dynamic friction to a contact that was active in the position solve
+pub(crate) fn solve_contact_vel(contact: &BodyContact, a: &mut SolverBody, b: &mut SolverBody, sub_dt: f32) {
+    let BodyContact { r_a, r_b, normal: n, normal_lagrange, .. } = *contact;
+    if normal_lagrange <= 0. {
+        return;
+    }
+
+    let relative_vel = a.point_velocity(r_a) - b.point_velocity(r_b);
+    let normal_vel = Vec2::dot(relative_vel, n);
+    let pre_solve_relative_vel = a.pre_solve_point_velocity(r_a) - b.pre_solve_point_velocity(r_b);
+    let pre_solve_normal_vel = Vec2::dot(pre_solve_relative_vel, n);
+
+    let restitution = (a.restitution + b.restitution) / 2.;
+
+    let w_a = a.generalized_inverse_mass(r_a, n);
+    let w_b = b.generalized_inverse_mass(r_b, n);
+
+    let restitution_velocity = (-restitution * pre_solve_normal_vel).min(0.);
+    let vel_impulse = n * ((-normal_vel + restitution_velocity) / (w_a + w_b));
+
+    let dynamic_coefficient = (a.dynamic_friction + b.dynamic_friction) / 2.;
+    let friction_impulse = dynamic_friction_impulse(
+        relative_vel,
+        n,
+        normal_lagrange,
+        dynamic_coefficient,
+        sub_dt,
+        |t| a.generalized_inverse_mass(r_a, t) + b.generalized_inverse_mass(r_b, t),
+    ).unwrap_or_default();
+
+    let impulse = vel_impulse + friction_impulse;
+    a.apply_velocity_impulse(r_a, impulse);
+    b.apply_velocity_impulse(r_b, -impulse);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{tasks::TaskPoolBuilder, utils::HashMap};
+
+    #[test]
+    fn static_friction_cone() {
+        // The contact points drifted 0.1 sideways; with unit inverse mass that takes an impulse of 0.1
+        let delta_p = Vec2::new(0.1, 0.02);
+        let impulse = static_friction_impulse(delta_p, Vec2::Y, 1., 0.5, |_| 1.).unwrap();
+        assert!((impulse - Vec2::new(-0.1, 0.)).length() < 0.0001);
+        // Outside the cone the bodies slide, and dynamic friction takes over
+        assert!(static_friction_impulse(delta_p, Vec2::Y, 0.19, 0.5, |_| 1.).is_none());
+        assert!(static_friction_impulse(Vec2::Y, Vec2::Y, 1., 0.5, |_| 1.).is_none());
+    }
+
+    #[test]
+    fn dynamic_friction_bound() {
+        let relative_vel = Vec2::new(2., -1.);
+        let sub_dt = 0.01;
+        // A light normal impulse slows the sliding by μ λ / h
+        let impulse = dynamic_friction_impulse(relative_vel, Vec2::Y, 1e-3, 0.5, sub_dt, |_| 2.).unwrap();
+        assert!((impulse - Vec2::new(-0.05 / 2., 0.)).length() < 0.0001);
+        // A heavy one at most stops it
+        let impulse = dynamic_friction_impulse(relative_vel, Vec2::Y, 1., 0.5, sub_dt, |_| 2.).unwrap();
+        assert!((impulse - Vec2::new(-2. / 2., 0.)).length() < 0.0001);
+        assert!(dynamic_friction_impulse(Vec2::Y, Vec2::Y, 1., 0.5, sub_dt, |_| 2.).is_none());
+    }
+
+    /// Eight overlapping stacks of four boxes on a shared ground, solved for a few substeps.
+    /// Returns the bits of every body's pose.
+    fn solve_stacks(pool: &TaskPool, order: ConstraintOrder) -> Vec<[u32; 3]> {
+        let ground = Entity::from_raw(0);
+        let mut bodies: HashMap<Entity, SolverBody> = HashMap::default();
+        bodies.insert(ground, SolverBody::default());
+        let mut contacts = Vec::new();
+        for stack in 0..8 {
+            for level in 0..4 {
+                let entity = Entity::from_raw(1 + stack * 4 + level);
+                let pos = Vec2::new(stack as f32 * 3. + 0.01 * level as f32, level as f32 * 0.95 + 0.45);
+                bodies.insert(entity, SolverBody {
+                    pos,
+                    rot: Rot::from_radians(0.01 * stack as f32),
+                    prev_pos: Some(pos),
+                    mass_inv: 1.,
+                    inertia_inv: 6.,
+                    static_friction: 0.5,
+                    dynamic_friction: 0.3,
+                    ..Default::default()
+                });
+                let below = if level == 0 { ground } else { Entity::from_raw(stack * 4 + level) };
+                for x in [-0.4, 0.4] {
+                    contacts.push(BodyContact {
+                        entity_a: entity,
+                        entity_b: below,
+                        local_r_a: Vec2::new(x, -0.5),
+                        local_r_b: if level == 0 { Vec2::new(pos.x + x, 0.) } else { Vec2::new(x, 0.5) },
+                        r_a: Vec2::ZERO,
+                        r_b: Vec2::ZERO,
+                        normal: -Vec2::Y,
+                        normal_lagrange: 0.,
+                    });
+                }
+            }
+        }
+
+        let mut islands = ConstraintIslands::default();
+        islands.build(&mut contacts, |entity| entity != ground, order == ConstraintOrder::GraphColoring);
+        for _ in 0..10 {
+            let gathered = gather_bodies(&islands, |entity| bodies[&entity]);
+            let solved = solve_constraints(pool, &islands, order, gathered, &mut contacts, |contact, a, b| {
+                solve_contact_pos(contact, a, b, 1. / 600.)
+            });
+            scatter_bodies(&islands, solved, |entity, body| {
+                bodies.insert(entity, body);
+            });
+        }
+
+        let mut entities: Vec<Entity> = bodies.keys().copied().collect();
+        entities.sort();
+        entities
+            .iter()
+            .map(|entity| {
+                let SolverBody { pos, rot, .. } = bodies[entity];
+                [pos.x.to_bits(), pos.y.to_bits(), rot.as_radians().to_bits()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn solve_independent_of_thread_count() {
+        let single = TaskPoolBuilder::new().num_threads(1).build();
+        let many = TaskPoolBuilder::new().num_threads(4).build();
+        for order in [ConstraintOrder::GaussSeidel, ConstraintOrder::GraphColoring] {
+            assert_eq!(solve_stacks(&single, order), solve_stacks(&many, order), "{:?}", order);
+        }
+    }
+}